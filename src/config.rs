@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::env::home_dir;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use lingua::Language;
+use serde::Deserialize;
+
+use crate::crawl::FileType;
+use crate::file_index::language_from_name;
+use crate::keywords::Algorithm;
+use crate::search::RankingWeights;
+
+/// User settings read from `config.toml`, every value has a default so the file is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub ranking: RankingWeights,
+    pub normalization: NormalizationConfig,
+    pub languages: LanguageConfig,
+    pub keywords: KeywordConfig,
+    pub corpus: CorpusConfig,
+    pub semantic: SemanticConfig,
+    /// File type name or extension to the command opening it, see `Openers`
+    pub openers: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SemanticConfig {
+    /// Word vectors in the word2vec/GloVe text format, the semantic index is only built with one
+    pub model: Option<String>,
+    /// Share of the semantic similarity in the blended score of `--semantic` searches
+    pub weight: f32,
+}
+
+impl Default for SemanticConfig {
+    fn default() -> Self {
+        Self { model: None, weight: 0.5 }
+    }
+}
+
+impl SemanticConfig {
+    pub fn model_path(&self) -> Option<PathBuf> {
+        let model = self.model.as_ref()?;
+        match model.strip_prefix("~/") {
+            Some(rest) => Some(home_dir().unwrap().join(rest)),
+            None => Some(PathBuf::from(model)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CorpusConfig {
+    /// Keyword terms found in at least this share of all documents become corpus stop words
+    pub stop_word_share: f64,
+    /// Below this many documents the shares say too little and nothing is learned
+    pub min_documents: i64,
+    /// Words that are always corpus stop words, like the company name
+    pub stop_words: Vec<String>,
+}
+
+impl Default for CorpusConfig {
+    fn default() -> Self {
+        Self { stop_word_share: 0.5, min_documents: 20, stop_words: vec![] }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct KeywordConfig {
+    /// Used for every file type without an entry in `file_types`
+    pub algorithm: Algorithm,
+    /// File type name or extension, like in `type:` filters, to algorithm
+    pub file_types: HashMap<String, Algorithm>,
+}
+
+impl KeywordConfig {
+    pub fn file_type_algorithms(&self) -> HashMap<FileType, Algorithm> {
+        self.file_types
+            .iter()
+            .filter_map(|(name, algorithm)| match FileType::from_name(name) {
+                Some(filetype) => Some((filetype, *algorithm)),
+                None => {
+                    eprintln!("[!] Ignoring unknown file type in config: {}", name);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LanguageConfig {
+    /// Languages documents are detected as, by english name or ISO 639-1 code. Empty means all
+    /// languages lingua knows.
+    pub detect: Vec<String>,
+}
+
+impl LanguageConfig {
+    /// The languages to choose from, empty for all. lingua needs at least two to choose from.
+    pub fn detection_languages(&self) -> Vec<Language> {
+        let languages: Vec<Language> = self
+            .detect
+            .iter()
+            .filter_map(|name| {
+                let language = language_from_name(name);
+                if language.is_none() {
+                    eprintln!("[!] Ignoring unknown language in config: {}", name);
+                }
+                language
+            })
+            .collect();
+
+        if languages.len() == 1 {
+            eprintln!("[!] Ignoring single language in config, add a second one to restrict detection: {}", languages[0]);
+            return vec![];
+        }
+        languages
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct NormalizationConfig {
+    /// Strip accents from keywords and filenames while indexing, "café" is stored as "cafe"
+    pub fold_accents: bool,
+}
+
+impl Default for NormalizationConfig {
+    fn default() -> Self {
+        Self { fold_accents: true }
+    }
+}
+
+impl Config {
+    pub fn opener_commands(&self) -> HashMap<FileType, String> {
+        self.openers
+            .iter()
+            .filter_map(|(name, command)| match FileType::from_name(name) {
+                Some(filetype) => Some((filetype, command.clone())),
+                None => {
+                    eprintln!("[!] Ignoring opener for unknown file type in config: {}", name);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> Self {
+        match read_to_string(path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+                eprintln!("[!] Ignoring invalid config {:?}: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+}
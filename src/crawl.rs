@@ -1,17 +1,26 @@
 use core::{panic};
 use rusqlite::types::FromSql;
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::{HashMap, HashSet};
 use std::convert::From;
 use std::fs::{self};
-use std::{path::PathBuf, time::UNIX_EPOCH};
+use std::{path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
 
 extern crate queues;
 use queues::*;
 
-use crate::file_index::{Indexer, Keyword};
+use crate::dupes::{clusters, content_hash, simhash, Signature};
+use crate::file_index::{ExtractionError, Indexer, Keyword};
+use crate::keywords::{Algorithm, CorpusFrequencies};
+use lingua::Language;
+use crate::fuzzy;
+use crate::normalize::{fold, query_forms};
+use crate::semantic::{to_blob, EmbeddingModel};
+use crate::snippet::Passage;
 
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FileType {
     Markdown = 1,
     Config = 2,
@@ -106,6 +115,51 @@ impl FileType {
     }
 }
 
+impl FileType {
+    /// Resolves names used in queries like `type:pdf`, falling back to known file extensions
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        let filetype = match name.as_str() {
+            "markdown" => Self::Markdown,
+            "config" => Self::Config,
+            "web" => Self::Web,
+            "python" => Self::Python,
+            "javascript" => Self::JavaScript,
+            "rust" => Self::Rust,
+            "csource" => Self::CSOURCE,
+            "presentation" | "slides" => Self::Presentation,
+            "librewriter" | "document" => Self::LibreWriter,
+            "excel" | "spreadsheet" => Self::Excel,
+            "plain" | "text" => Self::Plain,
+            _ => Self::get(Some(name)),
+        };
+
+        match filetype {
+            Self::Unknown => None,
+            ft => Some(ft),
+        }
+    }
+
+    /// Name in output formats, `from_name` resolves it back
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Markdown => "markdown",
+            Self::Config => "config",
+            Self::Web => "web",
+            Self::Python => "python",
+            Self::JavaScript => "javascript",
+            Self::Rust => "rust",
+            Self::CSOURCE => "csource",
+            Self::Presentation => "presentation",
+            Self::PDF => "pdf",
+            Self::LibreWriter => "librewriter",
+            Self::Excel => "excel",
+            Self::Plain => "plain",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
 impl From<usize> for FileType {
     fn from(value: usize) -> Self {
         FileType::from(value as i32)
@@ -115,41 +169,89 @@ impl From<usize> for FileType {
 #[derive(Debug)]
 struct IndexEntry {
     filename: String,
+    filename_normalized: String,
 
     filetype: FileType,
     filepath: PathBuf,
     keywords: Vec<Keyword>,
     last_modified_timestamp: u128,
+    title: Option<String>,
+    language: Option<String>,
+    // Comma separated, for documents written in several languages
+    languages: Option<String>,
+    language_confidence: Option<f64>,
+    // Only kept when crawling in full text mode
+    full_text: Option<String>,
+    passages: Vec<Passage>,
+    acronyms: Vec<(String, String)>,
+    // In bytes
+    filesize: u64,
+    content_hash: Option<String>,
+    simhash: Option<u64>,
 }
 
-#[derive(Debug)]
-struct SearchResult {
-    filename: String,
-    filepath: String,
-    filetype: FileType,
-    matching_keyword: String,
-    match_score: f32,
+/// Keywords of a file as (normalized form, score), with its index id if it is indexed
+pub struct DocumentKeywords {
+    pub si_id: Option<i64>,
+    pub keywords: Vec<(String, f32)>,
+}
+
+/// A query saved with `is save`, last run in milliseconds since the epoch
+pub struct SavedSearch {
+    pub name: String,
+    pub query: String,
+    pub last_run: Option<u64>,
 }
 
 pub struct CrawlDatabase {
     _conn: Connection,
     _search_queue: Queue<PathBuf>,
     _indexer: Indexer,
+    _store_full_text: bool,
+    _fold_accents: bool,
+    _stop_word_share: f64,
+    _stop_word_min_documents: i64,
+    _extra_stop_words: Vec<String>,
+    _embedding_model: Option<EmbeddingModel>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A plain text file with nothing extracted, tests set the fields they need
+    fn entry(name: &str) -> IndexEntry {
+        IndexEntry {
+            filename: String::from(name),
+            filename_normalized: String::from(name),
+            filetype: FileType::Plain,
+            filepath: PathBuf::from(format!("/test/{}", name)),
+            keywords: vec![],
+            last_modified_timestamp: 0,
+            title: None,
+            language: None,
+            languages: None,
+            language_confidence: None,
+            full_text: None,
+            passages: vec![],
+            acronyms: vec![],
+            filesize: 0,
+            content_hash: None,
+            simhash: None,
+        }
+    }
+
+    fn keyword(word: &str) -> Keyword {
+        Keyword { score: 1.0, word: String::from(word), normalized: String::from(word) }
+    }
+
     #[test]
     fn db_init_and_insert() {
         let mut cdb = CrawlDatabase::init(":memory:");
         let ie = IndexEntry {
-            filename: String::from("test_file_name.txt"),
             filetype: FileType::Config,
-            filepath: PathBuf::from("/test/test_file_name.txt"),
-            keywords: vec![],
             last_modified_timestamp: 92738728374,
+            ..entry("test_file_name.txt")
         };
 
         cdb.store_new_index(&ie);
@@ -173,6 +275,193 @@ mod tests {
         assert_eq!(e, FileType::Python);
     }
 
+    #[test]
+    fn failures_are_skipped_until_modified() {
+        let mut cdb = CrawlDatabase::init(":memory:");
+        let path = PathBuf::from("/test/broken.pdf");
+
+        cdb.record_extraction_failure(&path, 1000, ExtractionError::ParserError);
+        assert!(cdb.has_unchanged_failure("/test/broken.pdf", 1000));
+        assert!(!cdb.has_unchanged_failure("/test/broken.pdf", 2000));
+
+        cdb.clear_extraction_failure(&path);
+        assert!(!cdb.has_unchanged_failure("/test/broken.pdf", 1000));
+    }
+
+    #[test]
+    fn boilerplate_keywords_are_reweighted_to_nothing() {
+        let mut cdb = CrawlDatabase::init(":memory:");
+        cdb.set_corpus_stop_words(0.5, 2, vec![]);
+
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            cdb.store_new_index(&IndexEntry { keywords: vec![keyword("confidential"), keyword(name)], ..entry(name) });
+        }
+        assert_eq!(cdb.document_count("confidential"), 3);
+        cdb.update_index(&IndexEntry { keywords: vec![keyword("c.txt")], ..entry("c.txt") });
+        assert_eq!(cdb.document_count("confidential"), 2);
+
+        cdb.reweight_keywords();
+        let score = |word: &str| -> (f64, f64) {
+            cdb._conn
+                .query_row("SELECT score, raw_score FROM keywords WHERE word=?1", (word,), |r| Ok((r.get(0)?, r.get(1)?)))
+                .unwrap()
+        };
+        assert_eq!(score("a.txt").1, 1.0);
+        assert!(score("a.txt").0 > 1.0);
+        assert!(cdb.corpus_stop_words().contains("confidential"));
+    }
+
+    #[test]
+    fn removed_files_leave_nothing_behind() {
+        let mut cdb = CrawlDatabase::init(":memory:");
+        let ie = IndexEntry {
+            filetype: FileType::PDF,
+            keywords: vec![keyword("revenue")],
+            full_text: Some(String::from("revenue grew")),
+            ..entry("report.pdf")
+        };
+        cdb.store_new_index(&ie);
+        assert_eq!(cdb.document_count("revenue"), 1);
+
+        cdb.remove_index(&ie.filepath);
+        assert_eq!(cdb.index_id("/test/report.pdf"), None);
+        assert_eq!(cdb.document_count("revenue"), 0);
+        let leftovers: i64 = cdb
+            ._conn
+            .query_row("SELECT (SELECT COUNT(*) FROM keywords) + (SELECT COUNT(*) FROM fulltext)", (), |r| r.get(0))
+            .unwrap();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn full_text_is_kept_until_disabled() {
+        let mut cdb = CrawlDatabase::init(":memory:");
+        let mut ie = IndexEntry { full_text: Some(String::from("the old text")), ..entry("notes.txt") };
+        cdb.store_new_index(&ie);
+        let si_id = cdb.index_id("/test/notes.txt").unwrap();
+
+        ie.full_text = None;
+        cdb.update_index(&ie);
+        assert!(cdb.has_full_text(si_id));
+
+        cdb.disable_full_text();
+        assert!(!cdb.has_full_text(si_id));
+    }
+
+    #[test]
+    fn string_columns_are_migrated_to_text() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE vocabulary (word STRING PRIMARY KEY NOT NULL, document_count INT NOT NULL);
+            INSERT INTO vocabulary VALUES ('2024', 3), ('budget', 1);
+            CREATE TABLE vocabulary_trigrams (trigram STRING NOT NULL, word STRING NOT NULL REFERENCES vocabulary(word) ON DELETE CASCADE);
+            INSERT INTO vocabulary_trigrams VALUES ('202', '2024');",
+        )
+        .unwrap();
+
+        use_text_affinity(&conn, "vocabulary");
+        let words: Vec<String> = conn
+            .prepare("SELECT word FROM vocabulary ORDER BY word")
+            .unwrap()
+            .query_map((), |r| r.get(0))
+            .unwrap()
+            .map(|w| w.unwrap())
+            .collect();
+        assert_eq!(words, vec!["2024", "budget"]);
+        let trigrams: i64 = conn.query_row("SELECT COUNT(*) FROM vocabulary_trigrams", (), |r| r.get(0)).unwrap();
+        assert_eq!(trigrams, 1);
+    }
+
+    #[test]
+    fn migrated_search_index_keeps_its_references() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON;
+            CREATE TABLE search_index (id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, filepath STRING UNIQUE NOT NULL, title STRING);
+            INSERT INTO search_index (filepath, title) VALUES ('/test/a.md', '2024');
+            CREATE TABLE keywords (si_id REFERENCES search_index(id) ON DELETE CASCADE, word TEXT NOT NULL);
+            INSERT INTO keywords VALUES (1, 'budget');",
+        )
+        .unwrap();
+
+        use_text_affinity(&conn, "search_index");
+        let title: String = conn.query_row("SELECT title FROM search_index", (), |r| r.get(0)).unwrap();
+        assert_eq!(title, "2024");
+        conn.execute("DELETE FROM search_index", ()).unwrap();
+        let keywords: i64 = conn.query_row("SELECT COUNT(*) FROM keywords", (), |r| r.get(0)).unwrap();
+        assert_eq!(keywords, 0);
+    }
+
+    #[test]
+    fn numbers_in_the_vocabulary_are_read_as_words() {
+        let mut cdb = CrawlDatabase::init(":memory:");
+        cdb._conn
+            .execute("INSERT INTO search_index (filename, filetype, filepath) VALUES ('taxes.txt', 12, '/test/taxes.txt')", ())
+            .unwrap();
+        cdb._conn
+            .execute("INSERT INTO keywords (si_id, word, score) VALUES (1, '2024 tax return', 1.0)", ())
+            .unwrap();
+        cdb.rebuild_vocabulary();
+
+        let corpus = cdb.corpus_frequencies();
+        assert_eq!(corpus.document_counts.get("2024"), Some(&1));
+    }
+
+    #[test]
+    fn numeric_keyword_terms_are_reweighted() {
+        let mut cdb = CrawlDatabase::init(":memory:");
+        cdb.set_corpus_stop_words(0.5, 2, vec![]);
+        for name in ["a.txt", "b.txt"] {
+            cdb.store_new_index(&IndexEntry { keywords: vec![keyword("2024 report")], ..entry(name) });
+        }
+
+        cdb.reweight_keywords();
+        assert!(cdb.corpus_stop_words().contains("2024"));
+    }
+
+    #[test]
+    fn only_changed_content_counts_as_newly_indexed() {
+        let mut cdb = CrawlDatabase::init(":memory:");
+        let mut ie = IndexEntry { last_modified_timestamp: 1000, ..entry("notes.txt") };
+        cdb.store_new_index(&ie);
+        cdb._conn.execute("UPDATE search_index SET indexed_at=1", ()).unwrap();
+        let indexed_at = |cdb: &CrawlDatabase| -> u64 {
+            cdb._conn.query_row("SELECT indexed_at FROM search_index", (), |r| r.get(0)).unwrap()
+        };
+
+        // Backfilling the hash of an unchanged file
+        ie.content_hash = Some(String::from("abc"));
+        cdb.update_index(&ie);
+        assert_eq!(indexed_at(&cdb), 1);
+
+        ie.last_modified_timestamp = 2000;
+        cdb.update_index(&ie);
+        assert!(indexed_at(&cdb) > 1);
+    }
+
+    #[test]
+    fn saved_searches_may_be_numbers() {
+        let cdb = CrawlDatabase::init(":memory:");
+        cdb.save_search("2024", "2024");
+        let saved = cdb.saved_search("2024").unwrap();
+        assert_eq!((saved.name.as_str(), saved.query.as_str()), ("2024", "2024"));
+        cdb.list_saved_searches();
+    }
+
+    #[test]
+    fn files_without_known_words_count_as_embedded() {
+        let mut cdb = CrawlDatabase::init(":memory:");
+        let path = std::env::temp_dir().join("local_super_search_embedded_vectors.txt");
+        std::fs::write(&path, "1 2\ninvoice 1 0\n").unwrap();
+        cdb.set_embedding_model(EmbeddingModel::load(&path, None).unwrap());
+
+        cdb.store_new_index(&IndexEntry { keywords: vec![keyword("esperanto")], ..entry("eo.txt") });
+        let si_id = cdb.index_id("/test/eo.txt").unwrap();
+        assert!(cdb.is_embedded(si_id));
+        let vectors: i64 = cdb._conn.query_row("SELECT COUNT(*) FROM embeddings", (), |r| r.get(0)).unwrap();
+        assert_eq!(vectors, 0);
+    }
+
     #[test]
     fn filetype_from_sql(){
         // SQL
@@ -194,6 +483,98 @@ pub fn get_file_extension(name: &str) -> Option<String> {
     }
 }
 
+/// Terms of a keyword that document frequencies are counted for, stemmed words of its normalized form
+fn keyword_terms(normalized: &str) -> HashSet<String> {
+    normalized.split_whitespace().map(String::from).collect()
+}
+
+fn idf(documents: i64, document_count: i64) -> f64 {
+    (((documents + 1) as f64) / ((document_count + 1) as f64)).ln() + 1.0
+}
+
+/// Rare terms weigh more, corpus stop words nothing. Phrases get the mean of their terms, so
+/// "confidential report" is only dampened, not dropped.
+fn keyword_weight(
+    normalized: &str,
+    documents: i64,
+    document_count: impl Fn(&str) -> i64,
+    stop_words: &HashSet<String>,
+) -> f64 {
+    let terms: Vec<&str> = normalized.split_whitespace().collect();
+    if terms.is_empty() {
+        return 1.0;
+    }
+    let sum: f64 = terms
+        .iter()
+        .map(|t| if stop_words.contains(*t) { 0.0 } else { idf(documents, document_count(t)) })
+        .sum();
+    sum / terms.len() as f64
+}
+
+/// Counts in how many documents every keyword term occurs, for databases from before the
+/// counts were kept up to date while crawling
+fn rebuild_document_frequencies(conn: &Connection) {
+    conn.execute("DELETE FROM document_frequencies", ()).unwrap();
+    conn.execute(
+        "INSERT INTO document_frequencies (term, document_count)
+        SELECT term, COUNT(DISTINCT si_id) FROM (
+            WITH RECURSIVE split(si_id, term, rest) AS (
+                SELECT si_id, '', COALESCE(normalized, LOWER(word)) || ' ' FROM keywords
+                UNION ALL
+                SELECT si_id, SUBSTR(rest, 1, INSTR(rest, ' ') - 1), SUBSTR(rest, INSTR(rest, ' ') + 1)
+                FROM split WHERE rest <> ''
+            )
+            SELECT si_id, term FROM split WHERE term <> ''
+        ) GROUP BY term",
+        (),
+    )
+    .unwrap();
+}
+
+/// Languages of all indexed documents, needed to normalise query terms the same way
+pub(crate) fn indexed_languages(conn: &Connection) -> Vec<Language> {
+    let mut stmt = conn
+        .prepare("SELECT DISTINCT language FROM search_index WHERE language IS NOT NULL")
+        .unwrap();
+
+    stmt.query_map((), |r| r.get::<_, String>(0))
+        .unwrap()
+        .filter_map(|l| l.unwrap().parse().ok())
+        .collect()
+}
+
+/// Milliseconds since the epoch, how timestamps are stored
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+/// Milliseconds as a rough duration like "5 minutes" or "3 days"
+fn elapsed(millis: u64) -> String {
+    let minutes = millis / 60_000;
+    let (amount, unit) = match minutes {
+        0..60 => (minutes, "minute"),
+        60..1440 => (minutes / 60, "hour"),
+        _ => (minutes / 1440, "day"),
+    };
+    format!("{} {}{}", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+/// Databases created by older versions lack some columns, CREATE TABLE IF NOT EXISTS won't add them
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) {
+    let exists: bool = conn
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name=?2",
+            (table, column),
+            |r| r.get(0),
+        )
+        .unwrap();
+
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), ())
+            .unwrap();
+    }
+}
+
 #[derive(Debug,PartialEq)]
 enum FileCrawlStatus{
     FileNotChanged,
@@ -263,6 +644,35 @@ const EXCLUDE_DIRS: &[&str] = &[
 ];
 
 
+/// Columns declared STRING get NUMERIC affinity, which stores text like "2024" as a number that
+/// can't be read back as a String. Tables created that way by older versions are recreated with
+/// TEXT columns, has to run before their indexes are created.
+fn use_text_affinity(conn: &Connection, table: &str) {
+    let has_string_columns: bool = conn
+        .query_row("SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE type='STRING'", (table,), |r| r.get(0))
+        .unwrap();
+    if !has_string_columns {
+        return;
+    }
+
+    let sql: String = conn
+        .query_row("SELECT sql FROM sqlite_master WHERE type='table' AND name=?1", (table,), |r| r.get(0))
+        .unwrap();
+    let columns = sql[sql.find('(').unwrap()..].replace(" STRING", " TEXT");
+    // Dropping the old table must not cascade into the tables referencing it
+    conn.execute_batch(&format!(
+        "PRAGMA foreign_keys = OFF;
+        BEGIN;
+        CREATE TABLE {table}_text {columns};
+        INSERT INTO {table}_text SELECT * FROM {table};
+        DROP TABLE {table};
+        ALTER TABLE {table}_text RENAME TO {table};
+        COMMIT;
+        PRAGMA foreign_keys = ON;"
+    ))
+    .unwrap();
+}
+
 impl CrawlDatabase {
     pub fn init(path: &str) -> Self {
         let conn = Connection::open(path).unwrap();
@@ -274,144 +684,678 @@ impl CrawlDatabase {
             "CREATE TABLE IF NOT EXISTS search_index (
             id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
 
-            filename STRING NOT NULL,
+            filename TEXT NOT NULL,
             filetype INT NOT NULL,
-            filepath STRING UNIQUE NOT NULL,
-            last_modified_timestamp TIMESTAMP
+            filepath TEXT UNIQUE NOT NULL,
+            last_modified_timestamp TIMESTAMP,
+            title TEXT,
+            language TEXT,
+            filename_normalized TEXT,
+            languages TEXT,
+            language_confidence FLOAT,
+            filesize INT,
+            content_hash TEXT,
+            simhash INT
         ) ",
             (),
         )
         .unwrap();
+        add_column_if_missing(&conn, "search_index", "title", "TEXT");
+        add_column_if_missing(&conn, "search_index", "language", "TEXT");
+        add_column_if_missing(&conn, "search_index", "filename_normalized", "TEXT");
+        add_column_if_missing(&conn, "search_index", "languages", "TEXT");
+        add_column_if_missing(&conn, "search_index", "language_confidence", "FLOAT");
+        add_column_if_missing(&conn, "search_index", "filesize", "INT");
+        add_column_if_missing(&conn, "search_index", "content_hash", "TEXT");
+        add_column_if_missing(&conn, "search_index", "simhash", "INT");
+        // When the row was last written, for saved searches showing only new results
+        add_column_if_missing(&conn, "search_index", "indexed_at", "TIMESTAMP");
+        // Whether the file went through the semantic index, even if the model knew none of its words
+        add_column_if_missing(&conn, "search_index", "embedded", "INT NOT NULL DEFAULT 0");
+        // Titles and filenames like "2024" were stored as numbers
+        use_text_affinity(&conn, "search_index");
+        conn.execute("CREATE INDEX IF NOT EXISTS search_index_content_hash ON search_index(content_hash)", ())
+            .unwrap();
 
         conn.execute(
             "CREATE TABLE IF NOT EXISTS keywords (
             id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
             si_id REFERENCES search_index(id) ON DELETE CASCADE,
-            word STRING NOT NULL,
-            score FLOAT NOT NULL
+            word TEXT NOT NULL,
+            score FLOAT NOT NULL,
+            normalized TEXT,
+            raw_score FLOAT
+        ) ",
+            (),
+        )
+        .unwrap();
+        add_column_if_missing(&conn, "keywords", "normalized", "TEXT");
+        // Keywords like "2024" were stored as numbers
+        use_text_affinity(&conn, "keywords");
+        // score is the extractor's score weighed by inverse document frequency, raw_score what
+        // the extractor gave
+        add_column_if_missing(&conn, "keywords", "raw_score", "FLOAT");
+        conn.execute("UPDATE keywords SET raw_score = score WHERE raw_score IS NULL", ()).unwrap();
+
+        // Number of documents whose keywords contain a term, kept up to date while indexing
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS document_frequencies (
+            term TEXT PRIMARY KEY NOT NULL,
+            document_count INT NOT NULL
+        ) ",
+            (),
+        )
+        .unwrap();
+        use_text_affinity(&conn, "document_frequencies");
+        let frequencies_missing: bool = conn
+            .query_row(
+                "SELECT NOT EXISTS (SELECT 1 FROM document_frequencies) AND EXISTS (SELECT 1 FROM keywords)",
+                (),
+                |r| r.get(0),
+            )
+            .unwrap();
+        if frequencies_missing {
+            rebuild_document_frequencies(&conn);
+        }
+
+        // Paragraphs of every document, for the snippets shown below results
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS passages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            si_id REFERENCES search_index(id) ON DELETE CASCADE,
+            start_byte INT NOT NULL,
+            end_byte INT NOT NULL,
+            line INT NOT NULL,
+            content TEXT NOT NULL
+        ) ",
+            (),
+        )
+        .unwrap();
+        // Passages made of a number alone were stored as numbers
+        use_text_affinity(&conn, "passages");
+        conn.execute("CREATE INDEX IF NOT EXISTS passages_si_id ON passages(si_id)", ()).unwrap();
+        add_column_if_missing(&conn, "passages", "page", "INT NOT NULL DEFAULT 1");
+
+        // Embedding vectors for semantic search. Rows without a passage hold the vector of the
+        // document's keywords.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS embeddings (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            si_id REFERENCES search_index(id) ON DELETE CASCADE,
+            passage_id REFERENCES passages(id) ON DELETE CASCADE,
+            vector BLOB NOT NULL
+        ) ",
+            (),
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE search_index SET embedded=1 WHERE embedded=0 AND id IN (SELECT si_id FROM embeddings)",
+            (),
+        )
+        .unwrap();
+
+        // Acronyms defined in documents as "Full Name (ACR)", queries for either side find both
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS acronyms (
+            si_id REFERENCES search_index(id) ON DELETE CASCADE,
+            acronym STRING NOT NULL,
+            expansion STRING NOT NULL
+        ) ",
+            (),
+        )
+        .unwrap();
+
+        // Terms so common in this corpus that they say nothing about a document, like the company name
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS corpus_stop_words (
+            term TEXT PRIMARY KEY NOT NULL
+        ) ",
+            (),
+        )
+        .unwrap();
+        use_text_affinity(&conn, "corpus_stop_words");
+
+        // Results opened from searches, for ranking files we use often higher
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS open_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            si_id REFERENCES search_index(id) ON DELETE CASCADE,
+            opened_at TIMESTAMP NOT NULL
+        ) ",
+            (),
+        )
+        .unwrap();
+        conn.execute("CREATE INDEX IF NOT EXISTS open_history_si_id ON open_history(si_id)", ()).unwrap();
 
+        // Queries saved by name with `is save`, last_run is NULL until `is run` runs them
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS saved_searches (
+            name TEXT PRIMARY KEY NOT NULL,
+            query TEXT NOT NULL,
+            last_run TIMESTAMP
+        ) ",
+            (),
+        )
+        .unwrap();
+        use_text_affinity(&conn, "saved_searches");
+
+        // Files we could not extract keywords from, so they are not retried until they change
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS extraction_failures (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+
+            filepath STRING UNIQUE NOT NULL,
+            last_modified_timestamp TIMESTAMP,
+            reason STRING NOT NULL
         ) ",
             (),
         )
         .unwrap();
 
+        // Optional full text of documents, the rowid is the id of the search_index entry
+        conn.execute(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS fulltext USING fts5(content)",
+            (),
+        )
+        .unwrap();
+
+        // Distinct words of all keywords with a trigram index, used to correct typos in queries
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS vocabulary (
+            word TEXT PRIMARY KEY NOT NULL,
+            document_count INT NOT NULL
+        ) ",
+            (),
+        )
+        .unwrap();
+        use_text_affinity(&conn, "vocabulary");
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS vocabulary_trigrams (
+            trigram TEXT NOT NULL,
+            word TEXT NOT NULL REFERENCES vocabulary(word) ON DELETE CASCADE
+        ) ",
+            (),
+        )
+        .unwrap();
+        use_text_affinity(&conn, "vocabulary_trigrams");
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS vocabulary_trigrams_trigram ON vocabulary_trigrams(trigram)",
+            (),
+        )
+        .unwrap();
+
         Self {
             _conn: conn,
             _search_queue: queue![],
             _indexer: Indexer::init(),
+            _store_full_text: false,
+            _fold_accents: true,
+            _stop_word_share: 0.5,
+            _stop_word_min_documents: 20,
+            _extra_stop_words: vec![],
+            _embedding_model: None,
         }
     }
 
-    fn format_results(&self, sr: SearchResult) {
-        println!("{} [{} -> {}]", sr.filename, sr.match_score, sr.filepath);
+    /// Builds the semantic index while crawling, with vectors for the keywords and every passage
+    pub fn set_embedding_model(&mut self, model: EmbeddingModel) {
+        self._embedding_model = Some(model);
+    }
+
+    /// Terms in at least `share` of all documents become corpus stop words once the index has
+    /// `min_documents`. `extra` words are always treated as stop words.
+    pub fn set_corpus_stop_words(&mut self, share: f64, min_documents: i64, extra: Vec<String>) {
+        self._stop_word_share = share;
+        self._stop_word_min_documents = min_documents;
+        self._extra_stop_words = extra;
     }
 
-    pub fn search_keyword(&self, keyword: &str) {
+    /// Also store the extracted text of every crawled file, so that words which did not make it
+    /// into the keywords can still be found
+    pub fn enable_full_text(&mut self) {
+        self._store_full_text = true;
+    }
+
+    /// Deletes the stored full text of all files. Without this, files that have full text keep
+    /// it and get it updated when they change.
+    pub fn disable_full_text(&mut self) {
+        self._store_full_text = false;
+        self._conn.execute("DELETE FROM fulltext", ()).unwrap();
+    }
+
+    /// Whether accents are stripped from keywords and filenames while indexing. Queries match
+    /// either way.
+    pub fn set_accent_folding(&mut self, enabled: bool) {
+        self._fold_accents = enabled;
+        self._indexer.set_accent_folding(enabled);
+    }
+
+    pub fn set_detection_languages(&mut self, languages: &[Language]) {
+        self._indexer.set_detection_languages(languages);
+    }
 
+    pub fn set_keyword_algorithms(&mut self, default: Algorithm, algorithms: HashMap<FileType, Algorithm>) {
+        self._indexer.set_keyword_algorithms(default, algorithms);
+    }
 
-        // let mut stmt = self._conn.prepare("SELECT S.filename,S.filepath, S.filetype,K.word,K.score FROM keywords K INNER JOIN search_index S ON K.si_id=S.id
-        //                         WHERE K.word LIKE :search ORDER BY K.score DESC LIMIT 20").unwrap();
-        let mut stmt = self._conn.prepare("SELECT S.filename,S.filepath, S.filetype,K.word,SUM(K.score) FROM keywords K INNER JOIN search_index S ON K.si_id=S.id
-        WHERE K.word LIKE :search OR S.filename LIKE :search GROUP BY S.filepath ORDER BY SUM(K.score) DESC LIMIT 20").unwrap();
+    /// Document frequencies of the vocabulary built by the last crawl
+    fn corpus_frequencies(&self) -> CorpusFrequencies {
+        let documents = self
+            ._conn
+            .query_row("SELECT COUNT(*) FROM search_index", (), |r| r.get(0))
+            .unwrap();
+        let mut stmt = self._conn.prepare("SELECT word, document_count FROM vocabulary").unwrap();
+        let document_counts = stmt
+            .query_map((), |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        CorpusFrequencies { document_counts, documents }
+    }
 
+    pub(crate) fn connection(&self) -> &Connection {
+        &self._conn
+    }
 
-        let params = &[(":search",&format!("%{}%", keyword))];
-        let result_iter = stmt
-            .query_map(params, |row| {
-                Ok(SearchResult {
-                    filename: row.get(0)?,
-                    filepath: row.get(1)?,
-                    filetype: row.get(2)?,
-                    matching_keyword: row.get(3)?,
-                    match_score: row.get(4)?,
-                })
+    /// Stored keywords of an indexed file, other files are extracted now and weighted like a
+    /// crawl would
+    pub fn document_keywords(&mut self, file_path: &Path) -> Result<DocumentKeywords, ExtractionError> {
+        if let Some(si_id) = self.index_id(file_path.to_str().unwrap()) {
+            let mut stmt = self
+                ._conn
+                .prepare("SELECT COALESCE(normalized, LOWER(word)), score FROM keywords WHERE si_id=?1")
+                .unwrap();
+            let keywords = stmt
+                .query_map((si_id,), |r| Ok((r.get(0)?, r.get::<_, f64>(1)? as f32)))
+                .unwrap()
+                .map(|k| k.unwrap())
+                .collect();
+            return Ok(DocumentKeywords { si_id: Some(si_id), keywords });
+        }
+
+        let corpus = self.corpus_frequencies();
+        self._indexer.set_corpus_frequencies(corpus);
+        let document = self._indexer.extract_document(&file_path.to_path_buf())?;
+
+        let documents: i64 = self
+            ._conn
+            .query_row("SELECT COUNT(*) FROM search_index", (), |r| r.get(0))
+            .unwrap();
+        let stop_words = self.corpus_stop_words();
+        let keywords = document
+            .keywords
+            .into_iter()
+            .map(|kw| {
+                let weight = keyword_weight(&kw.normalized, documents, |t| self.document_count(t), &stop_words);
+                (kw.normalized, (kw.score as f64 * weight) as f32)
             })
+            .collect();
+        Ok(DocumentKeywords { si_id: None, keywords })
+    }
+
+    /// Remembers that a result was opened now, files that are not indexed are ignored
+    pub fn record_open(&self, file_path: &str) {
+        let Some(si_id) = self.index_id(file_path) else { return };
+        self._conn
+            .execute("INSERT INTO open_history (si_id, opened_at) VALUES (?1, ?2)", (si_id, now_millis()))
             .unwrap();
+    }
 
-        for search_result in result_iter {
-            match search_result {
-                Ok(sr) => self.format_results(sr),
-                Err(_) => {
-                    panic!("check here")
-                }
-            }
+    fn index_id(&self, file_path: &str) -> Option<i64> {
+        match self._conn.query_row("SELECT id FROM search_index WHERE filepath=?1", (file_path,), |r| r.get(0)) {
+            Ok(id) => Some(id),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(_) => panic!("Sqlite Error wtf"),
         }
+    }
 
+    fn has_full_text(&self, si_id: i64) -> bool {
+        self._conn
+            .query_row("SELECT COUNT(*) FROM fulltext WHERE rowid=?1", (si_id,), |r| r.get::<_, i64>(0))
+            .unwrap()
+            > 0
+    }
 
+    /// Replaces the full text of a document, entries without one keep what is stored
+    fn store_full_text(&mut self, si_id: i64, ie: &IndexEntry) {
+        let Some(text) = &ie.full_text else { return };
+        self._conn.execute("DELETE FROM fulltext WHERE rowid=?1", (si_id,)).unwrap();
+        self._conn
+            .execute("INSERT INTO fulltext (rowid, content) VALUES (?1, ?2)", (si_id, text))
+            .unwrap();
     }
 
-    fn store_new_index(&mut self, ie: &IndexEntry) {
-        self._conn.execute("INSERT INTO search_index (filename, filetype, filepath, last_modified_timestamp) VALUES (?1,?2,?3,?4)", (&ie.filename,ie.filetype as i64,ie.filepath.to_str(),ie.last_modified_timestamp as u64)).unwrap();
+    fn has_content_hash(&self, si_id: i64) -> bool {
+        self._conn
+            .query_row("SELECT content_hash IS NOT NULL FROM search_index WHERE id=?1", (si_id,), |r| r.get(0))
+            .unwrap()
+    }
 
-        let last_rowid = self._conn.last_insert_rowid();
+    fn store_passages(&mut self, si_id: i64, passages: &[Passage]) {
+        self._conn.execute("DELETE FROM passages WHERE si_id=?1", (si_id,)).unwrap();
 
-        for kw in &ie.keywords {
+        for p in passages {
             self._conn
                 .execute(
-                    "INSERT INTO keywords (si_id, word, score) VALUES (?1, ?2, ?3)",
-                    (last_rowid, &kw.word, kw.score),
+                    "INSERT INTO passages (si_id, start_byte, end_byte, line, page, content) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    (si_id, p.start, p.end, p.line, p.page, &p.text),
                 )
                 .unwrap();
-        };
+        }
+    }
+
+    fn is_embedded(&self, si_id: i64) -> bool {
+        self._conn
+            .query_row("SELECT embedded FROM search_index WHERE id=?1", (si_id,), |r| r.get(0))
+            .unwrap()
+    }
+
+    /// Needs the passages to be stored already, their vectors reference them
+    fn store_embeddings(&mut self, si_id: i64, ie: &IndexEntry) {
+        self._conn.execute("DELETE FROM embeddings WHERE si_id=?1", (si_id,)).unwrap();
+        // Marked even if no vector comes out, so such files aren't extracted again on every crawl
+        let embedded = self._embedding_model.is_some();
+        self._conn.execute("UPDATE search_index SET embedded=?1 WHERE id=?2", (embedded, si_id)).unwrap();
+        let Some(model) = &self._embedding_model else { return };
+
+        let keyword_words = ie
+            .keywords
+            .iter()
+            .flat_map(|kw| fuzzy::tokens(&kw.word).map(move |t| (t, kw.score)));
+        if let Some(vector) = model.embed_weighted(keyword_words) {
+            self._conn
+                .execute("INSERT INTO embeddings (si_id, vector) VALUES (?1, ?2)", (si_id, to_blob(&vector)))
+                .unwrap();
+        }
+
+        let mut stmt = self._conn.prepare("SELECT id, content FROM passages WHERE si_id=?1").unwrap();
+        let passages: Vec<(i64, String)> = stmt
+            .query_map((si_id,), |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .map(|p| p.unwrap())
+            .collect();
+        for (passage_id, content) in passages {
+            if let Some(vector) = model.embed(&content) {
+                self._conn
+                    .execute(
+                        "INSERT INTO embeddings (si_id, passage_id, vector) VALUES (?1, ?2, ?3)",
+                        (si_id, passage_id, to_blob(&vector)),
+                    )
+                    .unwrap();
+            }
+        }
+    }
+
+    fn store_acronyms(&mut self, si_id: i64, acronyms: &[(String, String)]) {
+        self._conn.execute("DELETE FROM acronyms WHERE si_id=?1", (si_id,)).unwrap();
+
+        for (acronym, expansion) in acronyms {
+            self._conn
+                .execute(
+                    "INSERT INTO acronyms (si_id, acronym, expansion) VALUES (?1, ?2, ?3)",
+                    (si_id, acronym, expansion),
+                )
+                .unwrap();
+        }
+    }
+
+    fn store_new_index(&mut self, ie: &IndexEntry) {
+        self._conn.execute("INSERT INTO search_index (filename, filetype, filepath, last_modified_timestamp, title, language, filename_normalized, languages, language_confidence, filesize, content_hash, simhash, indexed_at) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13)", (&ie.filename,ie.filetype as i64,ie.filepath.to_str(),ie.last_modified_timestamp as u64,&ie.title,&ie.language,&ie.filename_normalized,&ie.languages,ie.language_confidence,ie.filesize,&ie.content_hash,ie.simhash.map(|s| s as i64),now_millis())).unwrap();
+
+        let last_rowid = self._conn.last_insert_rowid();
+
+        self.store_keywords(last_rowid, &ie.keywords);
+        self.store_passages(last_rowid, &ie.passages);
+        self.store_embeddings(last_rowid, ie);
+        self.store_acronyms(last_rowid, &ie.acronyms);
+
+        self.store_full_text(last_rowid, ie);
     }
 
+    /// Reindexes a file. It only counts as changed for `is run --new-since-last` if its content
+    /// did, not when missing data like passages or hashes is filled in.
     fn update_index(&mut self, ie: &IndexEntry) {
-        self._conn.execute("UPDATE search_index SET filename=?1, filetype=?2, last_modified_timestamp=?3 WHERE filepath=?4",
+        self._conn.execute("UPDATE search_index SET filename=?1, filetype=?2, last_modified_timestamp=?3, title=?4, language=?5, filename_normalized=?6, languages=?7, language_confidence=?8, filesize=?9, content_hash=?10, simhash=?11,
+            indexed_at=CASE WHEN last_modified_timestamp IS NOT ?3 OR (content_hash IS NOT NULL AND content_hash IS NOT ?10) THEN ?12 ELSE indexed_at END
+            WHERE filepath=?13",
     (&ie.filename,
             ie.filetype as i64,
             ie.last_modified_timestamp as u64,
+            &ie.title,
+            &ie.language,
+            &ie.filename_normalized,
+            &ie.languages,
+            ie.language_confidence,
+            ie.filesize,
+            &ie.content_hash,
+            ie.simhash.map(|s| s as i64),
+            now_millis(),
             ie.filepath.to_str())).unwrap();
 
-        let last_rowid = self._conn.last_insert_rowid();
+        let last_rowid = self.index_id(ie.filepath.to_str().unwrap()).unwrap();
+
+        self.store_keywords(last_rowid, &ie.keywords);
+        self.store_passages(last_rowid, &ie.passages);
+        self.store_embeddings(last_rowid, ie);
+        self.store_acronyms(last_rowid, &ie.acronyms);
+
+        self.store_full_text(last_rowid, ie);
+    }
+
+    /// Drops a file and everything stored about it from the index
+    fn remove_index(&mut self, file_path: &Path) {
+        let Some(si_id) = self.index_id(file_path.to_str().unwrap()) else { return };
+        // Storing no keywords takes the old ones out of the document frequencies
+        self.store_keywords(si_id, &[]);
+        // The full text table has no foreign key to cascade from
+        self._conn.execute("DELETE FROM fulltext WHERE rowid=?1", (si_id,)).unwrap();
+        self._conn.execute("DELETE FROM search_index WHERE id=?1", (si_id,)).unwrap();
+    }
+
+    fn adjust_document_frequencies(&self, terms: &HashSet<String>, delta: i64) {
+        for term in terms {
+            self._conn
+                .execute(
+                    "INSERT INTO document_frequencies (term, document_count) VALUES (?1, ?2)
+                    ON CONFLICT(term) DO UPDATE SET document_count = document_count + ?2",
+                    (term, delta),
+                )
+                .unwrap();
+        }
+        self._conn.execute("DELETE FROM document_frequencies WHERE document_count <= 0", ()).unwrap();
+    }
+
+    fn document_count(&self, term: &str) -> i64 {
+        self._conn
+            .query_row("SELECT document_count FROM document_frequencies WHERE term=?1", (term,), |r| r.get(0))
+            .unwrap_or(0)
+    }
+
+    fn corpus_stop_words(&self) -> HashSet<String> {
+        let mut stmt = self._conn.prepare("SELECT term FROM corpus_stop_words").unwrap();
+        stmt.query_map((), |r| r.get(0)).unwrap().map(|r| r.unwrap()).collect()
+    }
+
+    /// Replaces the keywords of a document, keeping document frequencies up to date. Scores are
+    /// weighed with the frequencies as they are now, `reweight_keywords` evens them out after a crawl.
+    fn store_keywords(&mut self, si_id: i64, keywords: &[Keyword]) {
+        let old_terms: HashSet<String> = {
+            let mut stmt = self
+                ._conn
+                .prepare("SELECT COALESCE(normalized, LOWER(word)) FROM keywords WHERE si_id=?1")
+                .unwrap();
+            stmt.query_map((si_id,), |r| r.get::<_, String>(0))
+                .unwrap()
+                .flat_map(|n| keyword_terms(&n.unwrap()))
+                .collect()
+        };
+        self.adjust_document_frequencies(&old_terms, -1);
+        self._conn.execute("DELETE FROM keywords WHERE si_id=?1", (si_id,)).unwrap();
 
-        // Delete all old Keywords
-        self._conn.execute("DELETE FROM keywords WHERE si_id=?1",(last_rowid,)).unwrap();
+        let new_terms: HashSet<String> = keywords.iter().flat_map(|kw| keyword_terms(&kw.normalized)).collect();
+        self.adjust_document_frequencies(&new_terms, 1);
 
-        // And insert the new ones
-        for kw in &ie.keywords {
+        let documents: i64 = self
+            ._conn
+            .query_row("SELECT COUNT(*) FROM search_index", (), |r| r.get(0))
+            .unwrap();
+        let stop_words = self.corpus_stop_words();
+        for kw in keywords {
+            let weight = keyword_weight(&kw.normalized, documents, |t| self.document_count(t), &stop_words);
             self._conn
                 .execute(
-                    "INSERT INTO keywords (si_id, word, score) VALUES (?1, ?2, ?3)",
-                    (last_rowid, &kw.word, kw.score),
+                    "INSERT INTO keywords (si_id, word, score, normalized, raw_score) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    (si_id, &kw.word, kw.score as f64 * weight, &kw.normalized, kw.score),
                 )
                 .unwrap();
+        }
+    }
+
+    /// Learns the corpus stop words from the document frequencies and weighs every keyword's
+    /// raw score by the inverse document frequency of its terms
+    pub fn reweight_keywords(&mut self) {
+        let documents: i64 = self
+            ._conn
+            .query_row("SELECT COUNT(*) FROM search_index", (), |r| r.get(0))
+            .unwrap();
+        let document_counts: HashMap<String, i64> = {
+            let mut stmt = self._conn.prepare("SELECT term, document_count FROM document_frequencies").unwrap();
+            stmt.query_map((), |r| Ok((r.get(0)?, r.get(1)?)))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect()
         };
+
+        let mut learned: Vec<&String> = vec![];
+        if documents >= self._stop_word_min_documents {
+            learned = document_counts
+                .iter()
+                .filter(|(_, count)| **count as f64 / documents as f64 >= self._stop_word_share)
+                .map(|(term, _)| term)
+                .collect();
+            learned.sort();
+        }
+        let languages = indexed_languages(&self._conn);
+        let mut stop_words: HashSet<String> = learned.iter().map(|t| t.to_string()).collect();
+        for word in &self._extra_stop_words {
+            stop_words.extend(query_forms(word, &languages));
+        }
+
+        let keywords: Vec<(i64, f64, String)> = {
+            let mut stmt = self
+                ._conn
+                .prepare("SELECT id, COALESCE(raw_score, score), COALESCE(normalized, LOWER(word)) FROM keywords")
+                .unwrap();
+            stmt.query_map((), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect()
+        };
+
+        let tx = self._conn.transaction().unwrap();
+        tx.execute("DELETE FROM corpus_stop_words", ()).unwrap();
+        {
+            let mut insert_stop_word = tx.prepare("INSERT INTO corpus_stop_words (term) VALUES (?1)").unwrap();
+            for term in &stop_words {
+                insert_stop_word.execute((term,)).unwrap();
+            }
+            let mut update = tx.prepare("UPDATE keywords SET score=?1 WHERE id=?2").unwrap();
+            for (id, raw_score, normalized) in keywords {
+                let count = |t: &str| document_counts.get(t).copied().unwrap_or(0);
+                let weight = keyword_weight(&normalized, documents, count, &stop_words);
+                update.execute((raw_score * weight, id)).unwrap();
+            }
+        }
+        tx.commit().unwrap();
+
+        if !learned.is_empty() {
+            println!(
+                "Corpus stop words: {}",
+                learned.iter().map(|t| t.as_str()).collect::<Vec<&str>>().join(", ")
+            );
+        }
     }
 
     fn index_file(&mut self, file_path: &PathBuf) {
         let meta = fs::metadata(file_path).expect("Expected to be able to read MetaData on file");
+        let canonical_path = file_path.canonicalize().unwrap();
 
         // Check if modified time has changed
-        let last_modified = meta.modified().expect("Cannot read last modified");
-
-        let file_crawl_status = self.check_needs_crawl(file_path.to_str().unwrap(), last_modified.duration_since(UNIX_EPOCH).unwrap().as_millis());
-        if file_crawl_status == FileCrawlStatus::FileNotChanged  {return;}
-
+        let last_modified = meta
+            .modified()
+            .expect("Cannot read last modified")
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+
+        let mut file_crawl_status = self.check_needs_crawl(canonical_path.to_str().unwrap(), last_modified);
+        if file_crawl_status == FileCrawlStatus::FileNotChanged {
+            // Files indexed before full text mode, content hashes or the semantic index existed
+            // still need their text. Hashes came after passages, a file with one has its passages,
+            // even if its text had none.
+            match self.index_id(canonical_path.to_str().unwrap()) {
+                Some(si_id)
+                    if (self._store_full_text && !self.has_full_text(si_id))
+                        || !self.has_content_hash(si_id)
+                        || (self._embedding_model.is_some() && !self.is_embedded(si_id)) =>
+                {
+                    file_crawl_status = FileCrawlStatus::FileChanged;
+                }
+                Some(si_id) => {
+                    // Indexes from before sizes were stored get them without a reindex
+                    self._conn
+                        .execute("UPDATE search_index SET filesize=?1 WHERE id=?2 AND filesize IS NULL", (meta.len(), si_id))
+                        .unwrap();
+                    return;
+                }
+                None => return,
+            }
+        }
 
+        // Don't retry files that already failed, unless they were modified since
+        if self.has_unchanged_failure(canonical_path.to_str().unwrap(), last_modified) {return;}
 
-        let keywords = match self
+        let document = match self
             ._indexer
-            .get_keywords_from_path(file_path){
-                Ok(k) =>k,
-                Err(_err) => {
-                    println!("[!] {:?}",file_path);
+            .extract_document(file_path){
+                Ok(d) => d,
+                Err(reason) => {
+                    println!("[!] {:?} ({})", file_path, reason.description());
+                    self.record_extraction_failure(&canonical_path, last_modified, reason);
+                    // What was indexed before no longer matches the file
+                    self.remove_index(&canonical_path);
                     return;
                 }
             };
+        self.clear_extraction_failure(&canonical_path);
 
         let filename = file_path.file_name().unwrap().to_str().unwrap();
+        let text_simhash = simhash(&document.text);
+        // Full text stored by an earlier crawl is kept current, even if this one didn't ask for it
+        let store_full_text = self._store_full_text
+            || self.index_id(canonical_path.to_str().unwrap()).is_some_and(|si_id| self.has_full_text(si_id));
 
         let index_entry = IndexEntry {
             filename: String::from(filename),
+            filename_normalized: fold(filename, self._fold_accents),
             filetype: FileType::get(get_file_extension(filename)),
-            filepath: file_path.to_path_buf().canonicalize().unwrap(),
-            keywords: keywords,
-            last_modified_timestamp: meta
-                .modified()
-                .unwrap()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis(),
+            filepath: canonical_path,
+            keywords: document.keywords,
+            last_modified_timestamp: last_modified,
+            title: document.title,
+            language: Some(document.languages.primary.to_string()),
+            languages: Some(
+                document.languages.all.iter().map(|l| l.to_string()).collect::<Vec<String>>().join(","),
+            ),
+            language_confidence: Some(document.languages.confidence),
+            full_text: if store_full_text { Some(document.text) } else { None },
+            passages: document.passages,
+            acronyms: document.acronyms,
+            filesize: meta.len(),
+            // Unreadable files could not have been extracted, this only fails if it changed since
+            content_hash: fs::read(file_path).ok().map(|bytes| content_hash(&bytes)),
+            simhash: text_simhash,
         };
 
 
@@ -430,6 +1374,185 @@ impl CrawlDatabase {
 
     }
 
+    fn has_unchanged_failure(&self, file_path: &str, life_file_last_modified: u128) -> bool {
+        let params = &[(":fp", &String::from(file_path))];
+
+        let mut stmt = self._conn.prepare("SELECT last_modified_timestamp FROM extraction_failures WHERE filepath=:fp").unwrap();
+
+        match stmt.query_row(params, |r| {
+            let ts: u64 = r.get(0)?;
+            Ok(ts)
+        }) {
+            Ok(timestamp) => (timestamp as u128) == life_file_last_modified,
+            Err(rusqlite::Error::QueryReturnedNoRows) => false,
+            Err(_) => panic!("Sqlite Error wtf")
+        }
+    }
+
+    fn record_extraction_failure(&mut self, file_path: &Path, last_modified: u128, reason: ExtractionError) {
+        self._conn.execute("INSERT INTO extraction_failures (filepath, last_modified_timestamp, reason) VALUES (?1,?2,?3)
+            ON CONFLICT(filepath) DO UPDATE SET last_modified_timestamp=excluded.last_modified_timestamp, reason=excluded.reason",
+            (file_path.to_str(), last_modified as u64, reason.as_ref())).unwrap();
+    }
+
+    fn clear_extraction_failure(&mut self, file_path: &Path) {
+        self._conn.execute("DELETE FROM extraction_failures WHERE filepath=?1", (file_path.to_str(),)).unwrap();
+    }
+
+    /// Prints all files that could not be indexed, grouped by the reason they failed
+    pub fn list_extraction_failures(&self) {
+        let mut stmt = self._conn.prepare("SELECT reason, filepath FROM extraction_failures ORDER BY reason, filepath").unwrap();
+
+        let failures: Vec<(String, String)> = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|f| f.unwrap())
+            .collect();
+
+        if failures.is_empty() {
+            println!("No extraction failures recorded.");
+            return;
+        }
+
+        for group in failures.chunk_by(|a, b| a.0 == b.0) {
+            let reason = &group[0].0;
+            let description = match reason.parse::<ExtractionError>() {
+                Ok(r) => r.description(),
+                Err(_) => "Unknown reason",
+            };
+            println!("{} - {} ({} files)", reason, description, group.len());
+            for (_, filepath) in group {
+                println!("    {}", filepath);
+            }
+        }
+    }
+
+    /// Prints groups of indexed files with the same or nearly the same content, biggest first
+    pub fn list_duplicates(&self) {
+        let mut stmt = self
+            ._conn
+            .prepare("SELECT id, content_hash, simhash, filepath, filesize FROM search_index ORDER BY filepath")
+            .unwrap();
+        let mut files: HashMap<i64, (String, Option<u64>)> = HashMap::new();
+        let signatures: Vec<Signature> = stmt
+            .query_map((), |r| {
+                files.insert(r.get(0)?, (r.get(3)?, r.get(4)?));
+                Ok(Signature { si_id: r.get(0)?, content_hash: r.get(1)?, simhash: r.get::<_, Option<i64>>(2)?.map(|s| s as u64) })
+            })
+            .unwrap()
+            .map(|s| s.unwrap())
+            .collect();
+
+        let mut found = clusters(&signatures);
+        if found.is_empty() {
+            println!("No duplicates found.");
+            return;
+        }
+
+        found.sort_by_key(|c| std::cmp::Reverse(c.members.len()));
+        for cluster in found {
+            let kind = if cluster.identical { "identical" } else { "similar" };
+            println!("{} {} files", cluster.members.len(), kind);
+            for si_id in cluster.members {
+                let (filepath, filesize) = &files[&si_id];
+                match filesize {
+                    Some(size) => println!("    {} ({} bytes)", filepath, size),
+                    None => println!("    {}", filepath),
+                }
+            }
+        }
+    }
+
+    /// Saves the query under the name, replacing an earlier search of that name
+    pub fn save_search(&self, name: &str, query: &str) {
+        self._conn
+            .execute(
+                "INSERT INTO saved_searches (name, query) VALUES (?1, ?2)
+                ON CONFLICT(name) DO UPDATE SET query=excluded.query, last_run=NULL",
+                (name, query),
+            )
+            .unwrap();
+    }
+
+    pub fn saved_search(&self, name: &str) -> Option<SavedSearch> {
+        self._conn
+            .query_row("SELECT name, query, last_run FROM saved_searches WHERE name=?1", (name,), |r| {
+                Ok(SavedSearch { name: r.get(0)?, query: r.get(1)?, last_run: r.get(2)? })
+            })
+            .optional()
+            .unwrap()
+    }
+
+    pub fn mark_search_run(&self, name: &str, at: u64) {
+        self._conn.execute("UPDATE saved_searches SET last_run=?1 WHERE name=?2", (at, name)).unwrap();
+    }
+
+    /// Prints all saved searches with their queries and when they last ran
+    pub fn list_saved_searches(&self) {
+        let mut stmt = self._conn.prepare("SELECT name, query, last_run FROM saved_searches ORDER BY name").unwrap();
+        let saved: Vec<SavedSearch> = stmt
+            .query_map((), |r| Ok(SavedSearch { name: r.get(0)?, query: r.get(1)?, last_run: r.get(2)? }))
+            .unwrap()
+            .map(|s| s.unwrap())
+            .collect();
+
+        if saved.is_empty() {
+            println!("No saved searches, save one with: is save <name> <query>");
+            return;
+        }
+
+        let now = now_millis();
+        for search in saved {
+            let last_run = match search.last_run {
+                Some(at) => format!("last run {} ago", elapsed(now.saturating_sub(at))),
+                None => "never run".to_string(),
+            };
+            println!("{}: {} ({})", search.name, search.query, last_run);
+        }
+    }
+
+    /// Recreates the vocabulary from all stored keywords. Cheap compared to a crawl, and simpler
+    /// than keeping document counts right while files are updated.
+    pub fn rebuild_vocabulary(&mut self) {
+        let mut document_counts: HashMap<String, i64> = HashMap::new();
+        {
+            let mut stmt = self._conn.prepare("SELECT si_id, word FROM keywords ORDER BY si_id").unwrap();
+            let rows = stmt
+                .query_map((), |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))
+                .unwrap()
+                .map(|r| r.unwrap());
+
+            let mut current_document = None;
+            let mut seen_in_document: HashSet<String> = HashSet::new();
+            for (si_id, word) in rows {
+                if current_document != Some(si_id) {
+                    current_document = Some(si_id);
+                    seen_in_document.clear();
+                }
+                for token in fuzzy::tokens(&word) {
+                    if seen_in_document.insert(token.clone()) {
+                        *document_counts.entry(token).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        let tx = self._conn.transaction().unwrap();
+        tx.execute("DELETE FROM vocabulary_trigrams", ()).unwrap();
+        tx.execute("DELETE FROM vocabulary", ()).unwrap();
+        {
+            let mut insert_word = tx.prepare("INSERT INTO vocabulary (word, document_count) VALUES (?1, ?2)").unwrap();
+            let mut insert_trigram = tx.prepare("INSERT INTO vocabulary_trigrams (trigram, word) VALUES (?1, ?2)").unwrap();
+            for (word, count) in &document_counts {
+                insert_word.execute((word, count)).unwrap();
+                for trigram in fuzzy::trigrams(word) {
+                    insert_trigram.execute((trigram, word)).unwrap();
+                }
+            }
+        }
+        tx.commit().unwrap();
+    }
+
     fn check_needs_crawl(&self, file_path: &str, life_file_last_modified:u128)->FileCrawlStatus{
         let params = &[(":fp",&String::from(file_path))];
 
@@ -454,6 +1577,8 @@ impl CrawlDatabase {
     }
 
     pub fn start_crawl(&mut self, start_path: PathBuf) {
+        let corpus = self.corpus_frequencies();
+        self._indexer.set_corpus_frequencies(corpus);
 
         // We do BFS with queue
         let mut current_directory = start_path;
@@ -473,7 +1598,7 @@ impl CrawlDatabase {
                                 } else if meta.is_file() {
                                     let file_name = dir.file_name();
                                     let file_ext = get_file_extension(file_name.to_str().unwrap());
-                                    if !file_ext.is_none()
+                                    if file_ext.is_some()
                                         && INDEXABLE_FILE_EXTENSIONS
                                             .contains(&file_ext.unwrap().as_str())
                                     {
@@ -497,6 +1622,9 @@ impl CrawlDatabase {
             }
         }
 
+        self.reweight_keywords();
+        self.rebuild_vocabulary();
+
         println!("Finished!")
     }
 }
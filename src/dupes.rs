@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+use crate::fuzzy;
+
+// Signatures differing in at most this many bits belong to near-duplicates
+const SIMHASH_MAX_DISTANCE: u32 = 3;
+// With 4 bands of 16 bits, signatures within the distance share at least one band exactly
+const SIMHASH_BANDS: u32 = 4;
+// Signatures of shorter texts say too little, empty files would all be "near-duplicates"
+const SIMHASH_MIN_TOKENS: usize = 10;
+
+/// SHA-256 of the file content, equal for exact copies
+pub fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// FNV-1a, stable across runs and versions unlike the std hasher
+fn fnv1a(token: &str) -> u64 {
+    token.bytes().fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Charikar's SimHash of the words of the text, similar texts get signatures differing in few
+/// bits. None for texts too short to tell.
+pub fn simhash(text: &str) -> Option<u64> {
+    let mut weights = [0i64; 64];
+    let mut count = 0;
+    for token in fuzzy::tokens(text) {
+        count += 1;
+        let hash = fnv1a(&token);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash & (1 << bit) != 0 { 1 } else { -1 };
+        }
+    }
+    if count < SIMHASH_MIN_TOKENS {
+        return None;
+    }
+
+    Some(weights.iter().enumerate().filter(|(_, w)| **w > 0).fold(0, |hash, (bit, _)| hash | (1 << bit)))
+}
+
+/// What duplicates are found by, stored in `search_index`
+pub struct Signature {
+    pub si_id: i64,
+    pub content_hash: Option<String>,
+    pub simhash: Option<u64>,
+}
+
+/// Documents with the same or nearly the same content, in the order they were given
+#[derive(Debug, PartialEq)]
+pub struct Cluster {
+    pub members: Vec<i64>,
+    /// All members have the same content hash
+    pub identical: bool,
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[i] = root;
+    root
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    // The earlier document stays the root, so clusters keep the given order
+    parents[a.max(b)] = a.min(b);
+}
+
+/// Groups documents with equal content hashes or SimHashes within `SIMHASH_MAX_DISTANCE` bits.
+/// Documents without duplicates are left out.
+pub fn clusters(signatures: &[Signature]) -> Vec<Cluster> {
+    let mut parents: Vec<usize> = (0..signatures.len()).collect();
+
+    let mut by_hash: HashMap<&str, usize> = HashMap::new();
+    for (i, signature) in signatures.iter().enumerate() {
+        if let Some(hash) = &signature.content_hash {
+            let first = *by_hash.entry(hash).or_insert(i);
+            union(&mut parents, first, i);
+        }
+    }
+
+    // Only signatures sharing a band are compared, instead of every pair
+    let band_bits = 64 / SIMHASH_BANDS;
+    for band in 0..SIMHASH_BANDS {
+        let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, signature) in signatures.iter().enumerate() {
+            if let Some(simhash) = signature.simhash {
+                let key = (simhash >> (band * band_bits)) & ((1 << band_bits) - 1);
+                buckets.entry(key).or_default().push(i);
+            }
+        }
+        for bucket in buckets.values() {
+            for (n, &a) in bucket.iter().enumerate() {
+                for &b in &bucket[n + 1..] {
+                    let distance = (signatures[a].simhash.unwrap() ^ signatures[b].simhash.unwrap()).count_ones();
+                    if distance <= SIMHASH_MAX_DISTANCE {
+                        union(&mut parents, a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = vec![];
+    let mut group_of_root: HashMap<usize, usize> = HashMap::new();
+    for i in 0..signatures.len() {
+        let root = find(&mut parents, i);
+        let group = *group_of_root.entry(root).or_insert_with(|| {
+            groups.push(vec![]);
+            groups.len() - 1
+        });
+        groups[group].push(i);
+    }
+
+    groups
+        .into_iter()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let first = &signatures[members[0]].content_hash;
+            Cluster {
+                identical: first.is_some() && members.iter().all(|m| signatures[*m].content_hash == *first),
+                members: members.into_iter().map(|m| signatures[m].si_id).collect(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_and_edited_copies_are_clustered() {
+        let report = "The quarterly report shows revenue growth in all regions, driven by new customers \
+            in the north and lower costs for shipping, storage and the support team this year.";
+        let edited = report.replace("this year", "this quarter");
+        let other = "Holiday plans: we fly to the coast in July, rent a small house near the beach and \
+            visit the old town, the harbour and the museum of modern art on rainy days.";
+
+        let signature = |si_id: i64, text: &str| Signature {
+            si_id,
+            content_hash: Some(content_hash(text.as_bytes())),
+            simhash: simhash(text),
+        };
+        let signatures = vec![
+            signature(1, report),
+            signature(2, other),
+            signature(3, report),
+            signature(4, &edited),
+            Signature { si_id: 5, content_hash: None, simhash: simhash("too short") },
+        ];
+
+        assert_eq!(clusters(&signatures[..3]), vec![Cluster { members: vec![1, 3], identical: true }]);
+        assert_eq!(clusters(&signatures), vec![Cluster { members: vec![1, 3, 4], identical: false }]);
+    }
+}
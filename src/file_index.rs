@@ -1,115 +1,236 @@
-use std::{collections::HashMap, panic};
+use std::collections::HashMap;
 
 use std::fs::read_to_string;
 use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 
-use keyword_extraction::text_rank::{TextRank, TextRankParams};
 use lingua::{Language, LanguageDetector, LanguageDetectorBuilder};
-use stop_words::{get, LANGUAGE};
+use stop_words::get;
+
+use strum_macros::{AsRefStr, EnumString};
 
 use crate::crawl::{get_file_extension, FileType};
+use crate::keywords::{
+    Algorithm, CorpusFrequencies, KeywordExtractor, RakeExtractor, TextRankExtractor, TfIdfExtractor,
+    YakeExtractor,
+};
+use crate::normalize::normalize;
+use crate::segment::{contains_cjk, segment, WordAliases};
+use crate::snippet::{split_passages, Passage, PAGE_BREAK};
+use crate::synonyms::detect_acronyms;
 
 const AMOUNT_KEYWORDS: usize = 20;
-const LANG_ANALYSIS_FIRST_CHUNK: usize = 100;
-
-
-#[derive(Debug, Clone)]
-struct LanguageConversionFailError;
-
-fn stopword_lang_from_lingua_lang(
-    lang: lingua::Language,
-) -> Result<LANGUAGE, LanguageConversionFailError> {
-    // TODO: macro to use all languages here
-    match lang {
-        lingua::Language::German => Ok(LANGUAGE::German),
-        lingua::Language::English => Ok(LANGUAGE::English),
-        lingua::Language::Spanish => Ok(LANGUAGE::Spanish),
-        lingua::Language::French => Ok(LANGUAGE::French),
-        lingua::Language::Polish => Ok(LANGUAGE::Polish),
-        lingua::Language::Hebrew => Ok(LANGUAGE::Hebrew),
-        lingua::Language::Irish => Ok(LANGUAGE::Irish),
-        lingua::Language::Catalan => Ok(LANGUAGE::Catalan),
-        lingua::Language::Croatian => Ok(LANGUAGE::Croatian),
-        lingua::Language::Chinese => Ok(LANGUAGE::Chinese),
-        lingua::Language::Czech => Ok(LANGUAGE::Czech),
-        lingua::Language::Hindi => Ok(LANGUAGE::Hindi),
-        lingua::Language::Danish => Ok(LANGUAGE::Danish),
-        lingua::Language::Dutch => Ok(LANGUAGE::Dutch),
-        lingua::Language::Korean => Ok(LANGUAGE::Korean),
-
-        _ => Err(LanguageConversionFailError),
+const MAX_TITLE_LENGTH: usize = 120;
+// Language detection runs on several windows spread over the document instead of only its start,
+// which for PDFs is often a header, license or table of contents
+const LANG_ANALYSIS_WINDOWS: usize = 8;
+const LANG_ANALYSIS_WINDOW_CHARS: usize = 300;
+// Share of windows a language has to win to count as one of the document's languages
+const MIXED_LANGUAGE_SHARE: f64 = 0.25;
+// Frequency based stop words: at most this many, and never more than one in this many distinct words
+const FREQUENCY_STOP_WORDS: usize = 50;
+const FREQUENCY_STOP_WORD_SHARE: usize = 20;
+// Some malformed PDFs make pdf_extract spin for minutes, so we give up after a while
+const PDF_EXTRACTION_TIMEOUT: Duration = Duration::from_secs(30);
+// ISO 639-1 codes of the languages the stop_words crate has non empty lists for
+const STOP_WORD_LANGUAGES: &[&str] = &[
+    "af", "ar", "bg", "bn", "br", "ca", "cs", "da", "de", "el", "en", "eo", "es", "et", "eu", "fa", "fi", "fr",
+    "ga", "gl", "gu", "ha", "he", "hi", "hr", "hu", "hy", "id", "it", "ja", "ko", "ku", "la", "lt", "lv", "mr",
+    "ms", "nl", "no", "pl", "pt", "ro", "ru", "sk", "sl", "so", "st", "sv", "sw", "th", "tl", "tr", "uk", "ur",
+    "vi", "yo", "zh", "zu",
+];
+
+
+/// Stop word lists for a language, looked up by its ISO 639-1 code. stop_words panics for
+/// languages it has no list for, so only the ones in `STOP_WORD_LANGUAGES` are asked.
+fn stop_words_for(language: Language) -> Option<Vec<String>> {
+    let code = language.iso_code_639_1().to_string();
+    if !STOP_WORD_LANGUAGES.contains(&code.as_str()) {
+        return None;
+    }
+
+    Some(get(code)).filter(|w| !w.is_empty())
+}
+
+/// Without a stop word list, the words that make up most of a document are the best guess for
+/// stop words in any language
+fn frequency_stop_words(text: &str) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+        *counts.entry(word.to_lowercase()).or_insert(0) += 1;
     }
+
+    let mut by_frequency: Vec<(String, usize)> = counts.into_iter().collect();
+    by_frequency.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let amount = (by_frequency.len() / FREQUENCY_STOP_WORD_SHARE).min(FREQUENCY_STOP_WORDS);
+    by_frequency.into_iter().take(amount).map(|(w, _)| w).collect()
+}
+
+/// Resolves a language by its english name ("german") or ISO 639-1 code ("de")
+pub fn language_from_name(name: &str) -> Option<Language> {
+    Language::all().into_iter().find(|l| {
+        l.to_string().eq_ignore_ascii_case(name) || l.iso_code_639_1().to_string().eq_ignore_ascii_case(name)
+    })
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Keyword {
     pub score: f32,
     pub word: String,
+    // Folded and stemmed form that queries are matched against, `word` is kept for display
+    pub normalized: String,
 }
 
-// used very rarely, so clone should be fine
-impl From<&(String, f32)> for Keyword {
-    fn from(value: &(String, f32)) -> Self {
+impl Keyword {
+    fn new(ranked: &(String, f32), language: Language, fold_accents: bool) -> Self {
         Self {
-            score: value.1,
-            word: value.0.clone(),
+            score: ranked.1,
+            word: ranked.0.clone(),
+            normalized: normalize(&ranked.0, Some(language), fold_accents),
         }
     }
 }
 
-// We want to do caching for different language's stop_words
+/// Everything we get out of a single file during a crawl
+#[derive(Debug)]
+pub struct ExtractedDocument {
+    pub keywords: Vec<Keyword>,
+    pub text: String,
+    pub title: Option<String>,
+    pub languages: DetectedLanguages,
+    // Split before any cleanup, so offsets and line numbers point into the file as it is
+    pub passages: Vec<Passage>,
+    // (acronym, full name) pairs defined in the text
+    pub acronyms: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedLanguages {
+    pub primary: Language,
+    /// Average confidence of the primary language over all sampled windows, 0 to 1
+    pub confidence: f64,
+    /// Every language that dominates enough sections of the document, primary first
+    pub all: Vec<Language>,
+}
+
+/// Evenly spread windows over the text, starting and ending at whitespace so no words are cut
+fn sample_windows(text: &str) -> Vec<&str> {
+    let char_starts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    let total_chars = char_starts.len();
+    let byte_at = |char_index: usize| char_starts.get(char_index).copied().unwrap_or(text.len());
+
+    let window_starts: Vec<usize> = if total_chars <= LANG_ANALYSIS_WINDOWS * LANG_ANALYSIS_WINDOW_CHARS {
+        (0..total_chars).step_by(LANG_ANALYSIS_WINDOW_CHARS).collect()
+    } else {
+        let last_start = total_chars - LANG_ANALYSIS_WINDOW_CHARS;
+        (0..LANG_ANALYSIS_WINDOWS)
+            .map(|i| i * last_start / (LANG_ANALYSIS_WINDOWS - 1))
+            .collect()
+    };
+
+    window_starts
+        .into_iter()
+        .filter_map(|start| {
+            let window = &text[byte_at(start)..byte_at(start + LANG_ANALYSIS_WINDOW_CHARS)];
+            let trimmed = match (window.find(char::is_whitespace), window.rfind(char::is_whitespace)) {
+                (Some(first), Some(last)) if start > 0 && first < last => &window[first..last],
+                (_, Some(last)) if last > 0 => &window[..last],
+                _ => window,
+            };
+            let trimmed = trimmed.trim();
+            (!trimmed.is_empty()).then_some(trimmed)
+        })
+        .collect()
+}
+
+// We want to do caching for different language's stop_words, None if there is no list
 pub struct Indexer {
-    _stop_words_cache: HashMap<lingua::Language, Vec<String>>,
+    _stop_words_cache: HashMap<lingua::Language, Option<Vec<String>>>,
     _language_detector: LanguageDetector,
     _punctuation_replace: String,
+    _fold_accents: bool,
+    _default_algorithm: Algorithm,
+    _algorithms: HashMap<FileType, Algorithm>,
+    _corpus: CorpusFrequencies,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct CannotExtractKeywordsError {}
+/// Reasons why no keywords could be extracted from a file. The string form is what gets stored
+/// in the `extraction_failures` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumString, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum ExtractionError {
+    Unreadable,
+    UnsupportedType,
+    LanguageUndetected,
+    ParserError,
+    Timeout,
+}
+
+impl ExtractionError {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Unreadable => "File could not be read",
+            Self::UnsupportedType => "No parser for this file type",
+            Self::LanguageUndetected => "Language of the text could not be detected",
+            Self::ParserError => "Parser failed on malformed file",
+            Self::Timeout => "Parser took too long",
+        }
+    }
+}
 
-// TODO: do this with fancy macro because that sounds fun :9
 impl Indexer {
     pub fn init() -> Self {
-        let supported_languages = vec![
-            Language::German,
-            Language::English,
-            Language::Spanish,
-            Language::French,
-            Language::Polish,
-            Language::Hebrew,
-            Language::Irish,
-            Language::Catalan,
-            Language::Croatian,
-            Language::Chinese,
-            Language::Czech,
-            Language::Hindi,
-            Language::Danish,
-            Language::Dutch,
-            Language::Korean,
-        ];
-        let __language_detector =
-            LanguageDetectorBuilder::from_languages(&supported_languages).build();
+        // Models are loaded lazily, so detecting every language only costs for what we encounter
+        let __language_detector = LanguageDetectorBuilder::from_all_languages().build();
 
         let _bad_punctuation = String::from(r"“„");
 
         Self {
             _stop_words_cache: HashMap::new(),
             _language_detector: __language_detector,
-            _punctuation_replace: _bad_punctuation
+            _punctuation_replace: _bad_punctuation,
+            _fold_accents: true,
+            _default_algorithm: Algorithm::TextRank,
+            _algorithms: HashMap::new(),
+            _corpus: CorpusFrequencies::default(),
         }
     }
 
+    /// Keyword extraction algorithm for files without one of their own in `algorithms`
+    pub fn set_keyword_algorithms(&mut self, default: Algorithm, algorithms: HashMap<FileType, Algorithm>) {
+        self._default_algorithm = default;
+        self._algorithms = algorithms;
+    }
+
+    /// Document frequencies TF-IDF weighs words with
+    pub fn set_corpus_frequencies(&mut self, corpus: CorpusFrequencies) {
+        self._corpus = corpus;
+    }
+
+    pub fn set_accent_folding(&mut self, enabled: bool) {
+        self._fold_accents = enabled;
+    }
+
+    /// Restricts detection to the given languages, which makes it faster and more reliable
+    /// when we know what our documents are written in. Needs at least two languages.
+    pub fn set_detection_languages(&mut self, languages: &[Language]) {
+        self._language_detector = LanguageDetectorBuilder::from_languages(languages).build();
+    }
+
     /// Extract raw contents based on file type, then find out language for stop words and finally
-    /// find key words using TextRank
-    pub fn get_keywords_from_path(
+    /// find key words using the algorithm configured for the file type
+    pub fn extract_document(
         &mut self,
         file_path: &PathBuf,
-    ) -> Result<Vec<Keyword>, CannotExtractKeywordsError> {
+    ) -> Result<ExtractedDocument, ExtractionError> {
         // Identifying file type
         let ext = get_file_extension(file_path.file_name().unwrap().to_str().unwrap());
         let filetype = FileType::get(ext);
 
-        let content: Option<String> = match filetype {
+        let mut content: String = match filetype {
             // Plain text
             FileType::Markdown
             | FileType::Config
@@ -119,38 +240,22 @@ impl Indexer {
             | FileType::Rust
             | FileType::CSOURCE
             | FileType::Plain
-            | FileType::Excel => {
-                let c_res = read_to_string(&file_path);
-                match c_res {
-                    Ok(c) => Some(c),
-                    Err(_) => None,
-                }
-            }
+            | FileType::Excel => read_to_string(file_path).map_err(|_| ExtractionError::Unreadable)?,
 
             // Pdf Parsing
             FileType::PDF => {
-                let bytes_res = std::fs::read(&file_path);
-                match bytes_res {
-                    Ok(bytes) => {
-                        let out = panic::catch_unwind(||{
-                            pdf_extract::extract_text_from_mem(&bytes).expect("Encountered malformed PDF File. Skipping it")
-                        });
-
-                        match out {
-                            Ok(cont) => Some(cont),
-                            Err(_) => None,
-                        }
-                    }
-                    Err(_) => None,
-                }
+                let bytes = std::fs::read(file_path).map_err(|_| ExtractionError::Unreadable)?;
+                extract_pdf_text(bytes)?
             }
 
-            FileType::LibreWriter => None ,//todo!("Add parser for Word style documents"),
-            FileType::Presentation => None,//todo!("Add parser for presentation types"),
-            FileType::Unknown => None,
+            FileType::LibreWriter => return Err(ExtractionError::UnsupportedType), //todo!("Add parser for Word style documents"),
+            FileType::Presentation => return Err(ExtractionError::UnsupportedType), //todo!("Add parser for presentation types"),
+            FileType::Unknown => return Err(ExtractionError::UnsupportedType),
         };
 
-        let mut content = content.ok_or(CannotExtractKeywordsError {})?;
+        let passages = split_passages(&content);
+        let acronyms = detect_acronyms(&content);
+
         // Test for language
 
         //
@@ -160,54 +265,197 @@ impl Indexer {
             content = content.replace(c, "")
         }
 
-        // If not a big file, take all and analyse
-        let detection_text_snippet = if content.len() <= LANG_ANALYSIS_FIRST_CHUNK {
-            &content
-        } else {
-            &content.chars().take(LANG_ANALYSIS_FIRST_CHUNK).collect::<String>()
-        };
+        let languages = self
+            .detect_languages(&content)
+            .ok_or(ExtractionError::LanguageUndetected)?;
 
+        eprintln!("Language is: {:?} ({:.2})", languages.primary, languages.confidence);
 
-        let text_language = self
-            ._language_detector
-            .detect_language_of(detection_text_snippet)
-            .ok_or(CannotExtractKeywordsError {})?;
+        // Before segmenting, which would put spaces between the words of CJK titles
+        let title = extract_title(&content);
 
-        println!("Language is: {:?}", text_language);
+        // TextRank splits words on whitespace, which CJK scripts don't use
+        if languages
+            .all
+            .iter()
+            .any(|l| matches!(l, Language::Chinese | Language::Japanese | Language::Korean))
+        {
+            content = segment(&content);
+        }
 
-        // Stop words
-        let keywords = self
-            .extract_keywords(&content, text_language)
-            .expect("extract keywords, should not fail");
+        let keywords = self.extract_keywords(&content, &languages, filetype);
 
-         Ok(keywords)
+        Ok(ExtractedDocument {
+            keywords,
+            title,
+            text: content,
+            languages,
+            passages,
+            acronyms,
+        })
+    }
+
+    /// Detects the language of every sampled window and combines their confidence values
+    pub fn detect_languages(&self, text: &str) -> Option<DetectedLanguages> {
+        let mut confidence_sums: HashMap<Language, f64> = HashMap::new();
+        let mut wins: HashMap<Language, usize> = HashMap::new();
+        let mut windows = 0;
+
+        for window in sample_windows(text) {
+            let values = self._language_detector.compute_language_confidence_values(window);
+            // Values are sorted, the first one is the language of this window
+            let Some((top, top_confidence)) = values.first().copied() else { continue };
+            if top_confidence <= 0.0 {
+                continue;
+            }
+
+            windows += 1;
+            *wins.entry(top).or_insert(0) += 1;
+            for (language, confidence) in values {
+                *confidence_sums.entry(language).or_insert(0.0) += confidence;
+            }
+        }
+
+        let (primary, confidence_sum) = confidence_sums
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(l, c)| (*l, *c))?;
+
+        let needed_wins = ((windows as f64 * MIXED_LANGUAGE_SHARE).ceil() as usize).max(1);
+        let mut secondary: Vec<(Language, usize)> = wins
+            .into_iter()
+            .filter(|(l, w)| *l != primary && *w >= needed_wins)
+            .collect();
+        secondary.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        Some(DetectedLanguages {
+            primary,
+            confidence: confidence_sum / windows as f64,
+            all: [primary].into_iter().chain(secondary.into_iter().map(|(l, _)| l)).collect(),
+        })
     }
 
     fn extract_keywords(
         &mut self,
         raw_text: &str,
-        language: Language,
-    ) -> Result<Vec<Keyword>, LanguageConversionFailError> {
-        let stop_words = {
-            match self._stop_words_cache.get(&language) {
-                Some(l) => Ok(l.clone()),
-                None => {
-                    // If cache lookup fails
-                    let sw_lang = stopword_lang_from_lingua_lang(language)?;
-                    let internal_stop_words = get(sw_lang);
-                    self._stop_words_cache
-                        .insert(language, internal_stop_words.clone());
-
-                    Ok(internal_stop_words)
-                }
-            }?
+        languages: &DetectedLanguages,
+        filetype: FileType,
+    ) -> Vec<Keyword> {
+        // Mixed language documents get the stop words of all their languages
+        let mut stop_words: Vec<String> = vec![];
+        for language in &languages.all {
+            let list = self
+                ._stop_words_cache
+                .entry(*language)
+                .or_insert_with(|| stop_words_for(*language));
+            if let Some(list) = list {
+                stop_words.extend(list.iter().cloned());
+            }
+        }
+        if stop_words.is_empty() {
+            stop_words = frequency_stop_words(raw_text);
+        }
+        let language = languages.primary;
+
+        // The extractors would split segmented CJK words back into single characters
+        let aliased = contains_cjk(raw_text).then(|| WordAliases::of_cjk_words(raw_text, &stop_words));
+        let text = match &aliased {
+            Some(a) => a.text(),
+            None => raw_text,
         };
 
-        let text_rank = TextRank::new(TextRankParams::WithDefaults(raw_text, &stop_words));
-        Ok(text_rank
-            .get_ranked_phrase_scores(AMOUNT_KEYWORDS)
-            .iter()
-            .map(|i| Keyword::from(i))
-            .collect())
+        let algorithm = self._algorithms.get(&filetype).copied().unwrap_or(self._default_algorithm);
+        let extractor: Box<dyn KeywordExtractor + '_> = match algorithm {
+            Algorithm::TextRank => Box::new(TextRankExtractor),
+            Algorithm::Rake => Box::new(RakeExtractor),
+            Algorithm::Yake => Box::new(YakeExtractor),
+            Algorithm::TfIdf => Box::new(TfIdfExtractor { corpus: &self._corpus }),
+        };
+        let ranked = extractor.extract(text, &stop_words, AMOUNT_KEYWORDS);
+
+        // Every algorithm scores on its own scale, the best keyword of a document gets 1.0 so
+        // files indexed with different algorithms rank alike. This includes TextRank: files
+        // indexed before keep their raw TextRank scores until they are crawled again.
+        let best = ranked.iter().map(|(_, score)| *score).fold(0.0, f32::max);
+        ranked
+            .into_iter()
+            .map(|(phrase, score)| (phrase, if best > 0.0 { score / best } else { score }))
+            .map(|(phrase, score)| match &aliased {
+                Some(a) => (a.restore(&phrase), score),
+                None => (phrase, score),
+            })
+            .map(|ranked| Keyword::new(&ranked, language, self._fold_accents))
+            .collect()
     }
-}
\ No newline at end of file
+}
+
+/// The first non empty line is the best guess for a title we have across all file types.
+/// Markdown heading markers are stripped.
+pub fn extract_title(text: &str) -> Option<String> {
+    let line = text
+        .lines()
+        .map(|l| l.trim().trim_start_matches('#').trim())
+        .find(|l| !l.is_empty())?;
+
+    Some(line.chars().take(MAX_TITLE_LENGTH).collect())
+}
+
+/// pdf_extract panics on malformed files and can hang on others, so it runs on its own thread.
+/// Threads can't be stopped, one that times out is left running until the parser returns.
+fn extract_pdf_text(bytes: Vec<u8>) -> Result<String, ExtractionError> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // Pages are kept apart by form feeds on their own line, so passages know their page
+        let text = pdf_extract::extract_text_from_mem_by_pages(&bytes)
+            .map(|pages| pages.join(&format!("\n{}\n", PAGE_BREAK)));
+        // Receiver is gone if we already timed out
+        let _ = tx.send(text);
+    });
+
+    match rx.recv_timeout(PDF_EXTRACTION_TIMEOUT) {
+        Ok(Ok(text)) => Ok(text),
+        Ok(Err(_)) => Err(ExtractionError::ParserError),
+        // Sender dropped without sending: the parser panicked
+        Err(RecvTimeoutError::Disconnected) => Err(ExtractionError::ParserError),
+        Err(RecvTimeoutError::Timeout) => Err(ExtractionError::Timeout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_words_are_found_by_iso_code() {
+        assert!(stop_words_for(Language::Italian).is_some_and(|w| w.contains(&String::from("della"))));
+        assert!(stop_words_for(Language::Portuguese).is_some());
+        // Every language lingua detects either has a list or falls back without panicking
+        for language in Language::all() {
+            stop_words_for(language);
+        }
+    }
+
+    #[test]
+    fn frequent_words_become_stop_words() {
+        let text = "ka tolo ka mesi ka ruba ka tolo nimi ka sewi ".repeat(10)
+            + &(0..100).map(|i| format!("w{} ", i)).collect::<String>();
+        let stop_words = frequency_stop_words(&text);
+        assert_eq!(stop_words[0], "ka");
+        assert!(!stop_words.contains(&String::from("w7")));
+    }
+
+    #[test]
+    fn mixed_language_documents_are_detected_per_section() {
+        let english = "The annual report describes how the company performed during the last year, \
+            which markets grew and where the management sees opportunities for the coming years. ";
+        let german = "Der Jahresbericht beschreibt, wie sich das Unternehmen im letzten Jahr entwickelt hat, \
+            welche Märkte gewachsen sind und wo die Geschäftsführung Chancen für die kommenden Jahre sieht. ";
+        let text = english.repeat(8) + &german.repeat(8);
+
+        let indexer = Indexer::init();
+        let detected = indexer.detect_languages(&text).unwrap();
+        assert!(detected.all.contains(&Language::English), "{:?}", detected);
+        assert!(detected.all.contains(&Language::German), "{:?}", detected);
+        assert!(detected.confidence > 0.0 && detected.confidence <= 1.0);
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::HashSet;
+
+/// Words are split into lowercase alphanumeric tokens before they go into the vocabulary
+pub fn tokens(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+}
+
+/// Character trigrams of the word padded with `$`, so short words and word edges get trigrams too
+pub fn trigrams(word: &str) -> HashSet<String> {
+    let padded: Vec<char> = format!("${}$", word).chars().collect();
+    padded.windows(3).map(|w| w.iter().collect()).collect()
+}
+
+/// How many edits we tolerate before a vocabulary word is too far from what was typed
+pub fn max_distance(word: &str) -> usize {
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=6 => 1,
+        _ => 2,
+    }
+}
+
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levenshtein_distances() {
+        assert_eq!(levenshtein("kubernets", "kubernetes"), 1);
+        assert_eq!(levenshtein("invoice", "invoice"), 0);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("straße", "strasse"), 2);
+    }
+
+    #[test]
+    fn trigrams_are_padded() {
+        let t = trigrams("ab");
+        assert!(t.contains("$ab") && t.contains("ab$"));
+        assert_eq!(t.len(), 2);
+    }
+}
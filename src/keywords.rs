@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use keyword_extraction::rake::{Rake, RakeParams};
+use keyword_extraction::text_rank::{TextRank, TextRankParams};
+use keyword_extraction::yake::{Yake, YakeParams};
+use serde::Deserialize;
+
+use crate::fuzzy;
+
+/// Finds the most important words or phrases of a document, best first
+pub trait KeywordExtractor {
+    fn extract(&self, text: &str, stop_words: &[String], amount: usize) -> Vec<(String, f32)>;
+}
+
+/// Algorithms that can be picked per file type in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    #[default]
+    TextRank,
+    Rake,
+    Yake,
+    TfIdf,
+}
+
+/// Graph based ranking of co-occurring words, works best on longer prose
+pub struct TextRankExtractor;
+
+impl KeywordExtractor for TextRankExtractor {
+    fn extract(&self, text: &str, stop_words: &[String], amount: usize) -> Vec<(String, f32)> {
+        TextRank::new(TextRankParams::WithDefaults(text, stop_words)).get_ranked_phrase_scores(amount)
+    }
+}
+
+/// Splits phrases at stop words and punctuation, suits short and list-like files such as configs
+pub struct RakeExtractor;
+
+impl KeywordExtractor for RakeExtractor {
+    fn extract(&self, text: &str, stop_words: &[String], amount: usize) -> Vec<(String, f32)> {
+        Rake::new(RakeParams::WithDefaults(text, stop_words)).get_ranked_phrases_scores(amount)
+    }
+}
+
+/// Statistical features of single documents like casing, position and spread of a term
+pub struct YakeExtractor;
+
+impl KeywordExtractor for YakeExtractor {
+    fn extract(&self, text: &str, stop_words: &[String], amount: usize) -> Vec<(String, f32)> {
+        Yake::new(YakeParams::WithDefaults(text, stop_words)).get_ranked_keyword_scores(amount)
+    }
+}
+
+/// How many documents of the index contain a word, loaded before a crawl
+#[derive(Debug, Default, Clone)]
+pub struct CorpusFrequencies {
+    pub document_counts: HashMap<String, i64>,
+    pub documents: i64,
+}
+
+impl CorpusFrequencies {
+    /// Smoothed, so words never seen before get the highest weight instead of dividing by zero
+    pub fn idf(&self, word: &str) -> f32 {
+        let count = self.document_counts.get(word).copied().unwrap_or(0);
+        (((self.documents + 1) as f32) / ((count + 1) as f32)).ln() + 1.0
+    }
+}
+
+/// Term frequency in the document weighed by how rare the word is across the index. On the
+/// first crawl there is no corpus yet and this ranks by term frequency alone.
+pub struct TfIdfExtractor<'a> {
+    pub corpus: &'a CorpusFrequencies,
+}
+
+impl KeywordExtractor for TfIdfExtractor<'_> {
+    fn extract(&self, text: &str, stop_words: &[String], amount: usize) -> Vec<(String, f32)> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut total = 0;
+        for token in fuzzy::tokens(text) {
+            total += 1;
+            if stop_words.contains(&token) || token.chars().all(|c| c.is_numeric()) {
+                continue;
+            }
+            *counts.entry(token).or_insert(0) += 1;
+        }
+
+        let mut scored: Vec<(String, f32)> = counts
+            .into_iter()
+            .map(|(word, count)| {
+                let score = count as f32 / total as f32 * self.corpus.idf(&word);
+                (word, score)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scored.truncate(amount);
+        scored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tf_idf_prefers_words_rare_in_the_corpus() {
+        let corpus = CorpusFrequencies {
+            document_counts: HashMap::from([(String::from("report"), 99)]),
+            documents: 100,
+        };
+        let extractor = TfIdfExtractor { corpus: &corpus };
+        let keywords = extractor.extract("report report report kubernetes kubernetes cluster", &[], 2);
+        assert_eq!(keywords[0].0, "kubernetes", "{:?}", keywords);
+    }
+
+    #[test]
+    fn every_algorithm_finds_keywords() {
+        let text = "Kubernetes schedules containers onto nodes. The scheduler watches new containers \
+            and assigns every container to a node with enough memory. Nodes report memory usage.";
+        let stop_words: Vec<String> = ["the", "and", "to", "a", "with", "onto", "every"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let corpus = CorpusFrequencies::default();
+        let extractors: [&dyn KeywordExtractor; 4] =
+            [&TextRankExtractor, &RakeExtractor, &YakeExtractor, &TfIdfExtractor { corpus: &corpus }];
+
+        for extractor in extractors {
+            let keywords = extractor.extract(text, &stop_words, 5);
+            assert!(!keywords.is_empty());
+            assert!(keywords[0].1 > 0.0, "{:?}", keywords);
+        }
+    }
+}
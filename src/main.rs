@@ -1,22 +1,151 @@
-use std::{env::home_dir, path::PathBuf, process::exit};
+use std::collections::HashSet;
+use std::{env::{current_dir, home_dir}, path::PathBuf, process::{exit, Command}};
 
-use clap::Parser;
-use search::search_through_database;
+use clap::{Parser, Subcommand};
+use open::{reveal_command, Openers};
+use search::{rank_similar, search_through_database, SearchOptions, SearchPage, SemanticSearch, SortOrder, RESULT_LIMIT};
+use output::{print_explained, print_results, OutputFormat};
+use semantic::EmbeddingModel;
+use synonyms::Synonyms;
 
+mod config;
 mod crawl;
+mod dupes;
 mod file_index;
+mod fuzzy;
+mod keywords;
+mod normalize;
+mod open;
+mod output;
 mod search;
+mod segment;
+mod semantic;
+mod snippet;
+mod synonyms;
+mod tui;
 
 #[derive(Parser)]
 #[command(name = "Indexed Search")]
 #[command(version = "0.0.1")]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     #[arg(short, long, value_name = "crawl")]
     crawl_dir: Option<String>,
 
+    /// Also store the full text of crawled files, so any word in them can be searched
+    #[arg(short, long, requires = "crawl_dir")]
+    full_text: bool,
+
+    /// Delete the full text stored by earlier crawls, crawls without --full-text keep it up to date
+    #[arg(long, requires = "crawl_dir", conflicts_with = "full_text")]
+    no_full_text: bool,
+
+    /// How results are printed, see the Readme for the schema of the machine readable formats
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Print only the paths, each terminated by a NUL byte for `xargs -0`, same as `--format null`
+    #[arg(long, conflicts_with = "format")]
+    print0: bool,
+
+    /// Number of results to show
+    #[arg(long, value_name = "N", default_value_t = RESULT_LIMIT, conflicts_with = "crawl_dir")]
+    limit: usize,
+
+    /// Skip this many of the best results
+    #[arg(long, value_name = "N", default_value_t = 0, conflicts_with_all = ["crawl_dir", "page"])]
+    offset: usize,
+
+    /// Show the N-th page of `--limit` results, starting at 1
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "crawl_dir")]
+    page: Option<u64>,
+
+    /// Order of the results
+    #[arg(long, value_enum, default_value_t = SortOrder::Score, conflicts_with = "crawl_dir")]
+    sort: SortOrder,
+
+    /// Only search files in this directory and below
+    #[arg(long = "in", value_name = "DIR", conflicts_with = "crawl_dir")]
+    in_dir: Option<String>,
+
+    /// Only search files in the current directory and below
+    #[arg(long, conflicts_with_all = ["crawl_dir", "in_dir"])]
+    here: bool,
+
+    /// Show files with the same or nearly the same content as one result
+    #[arg(long, conflicts_with = "crawl_dir")]
+    collapse_duplicates: bool,
+
+    /// Only print how many files match
+    #[arg(long, conflicts_with_all = ["crawl_dir", "interactive", "open", "first", "reveal"])]
+    count: bool,
+
+    /// Find documents similar to this file, by the keywords they share
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["crawl_dir", "interactive", "semantic", "search_term"])]
+    like: Option<String>,
+
+    /// Show how the score of every result was built
+    #[arg(long, conflicts_with_all = ["crawl_dir", "interactive", "format", "print0", "count", "open", "first", "reveal"])]
+    explain: bool,
+
+    /// Open the N-th result instead of listing them, with the opener for its file type
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["crawl_dir", "interactive"])]
+    open: Option<u64>,
+
+    /// Open the first result, same as `--open 1`
+    #[arg(long, conflicts_with_all = ["open", "crawl_dir", "interactive"])]
+    first: bool,
+
+    /// Open the folder containing the result, the first one without `--open`
+    #[arg(long, conflicts_with_all = ["crawl_dir", "interactive"])]
+    reveal: bool,
+
+    /// Also rank by meaning, using the embedding model from the config
+    #[arg(short, long, conflicts_with = "crawl_dir")]
+    semantic: bool,
+
+    /// Search as you type in a terminal UI, starting with the search term if one is given
+    #[arg(short, long, conflicts_with = "crawl_dir")]
+    interactive: bool,
+
     search_term: Option<String>,
 }
 
+#[derive(Subcommand)]
+enum Commands {
+    /// List files that could not be indexed, grouped by reason
+    Failures,
+    /// List groups of files with the same or nearly the same content
+    Dupes,
+    /// Save a query under a name, to run it later with `is run`
+    Save {
+        name: String,
+        /// The query, with the same syntax as a search
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        query: Vec<String>,
+    },
+    /// List saved searches
+    Saved,
+    /// Run a saved search
+    Run {
+        name: String,
+        /// Only show files added or changed since the search last ran, for cron jobs
+        #[arg(long)]
+        new_since_last: bool,
+        /// Send a desktop notification with notify-send when there are results
+        #[arg(long)]
+        notify: bool,
+        /// Number of results to show
+        #[arg(long, value_name = "N", default_value_t = RESULT_LIMIT)]
+        limit: usize,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+}
+
 fn database_location() -> String {
     String::from(
         home_dir()
@@ -27,34 +156,254 @@ fn database_location() -> String {
     )
 }
 
+fn config_location() -> PathBuf {
+    home_dir()
+        .unwrap()
+        .join(".config/local_super_search/config.toml")
+}
+
+fn synonyms_location() -> PathBuf {
+    config_location().with_file_name("synonyms.txt")
+}
+
+/// Paths given with `--in=~/dir` are not expanded by the shell
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => home_dir().unwrap().join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Runs the opener and waits for it, so terminal programs like editors work too
+fn open_result(cdb: &crawl::CrawlDatabase, page: &SearchPage, n: usize, reveal: bool, openers: &Openers) {
+    let Some(sr) = page.results.first() else {
+        println!("No result {} to open, the search found {}.", n, page.total);
+        exit(1);
+    };
+
+    let mut command = if reveal { reveal_command(&sr.filepath) } else { openers.command(sr) };
+    println!("Opening {}", sr.filepath);
+    // Showing the folder doesn't mean the file was used, it shouldn't gain frecency
+    if !reveal {
+        cdb.record_open(&sr.filepath);
+    }
+    match command.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            eprintln!("[!] Opener failed with {}", status);
+            exit(1);
+        }
+        Err(e) => {
+            eprintln!("[!] Cannot run opener: {}", e);
+            exit(1);
+        }
+    }
+}
+
+/// The database with the indexing settings of the config, for crawls and files extracted on the fly
+fn configured_database(config: &config::Config) -> crawl::CrawlDatabase {
+    let mut crawl_database = crawl::CrawlDatabase::init(&database_location());
+    crawl_database.set_accent_folding(config.normalization.fold_accents);
+    let languages = config.languages.detection_languages();
+    if !languages.is_empty() {
+        crawl_database.set_detection_languages(&languages);
+    }
+    crawl_database.set_keyword_algorithms(config.keywords.algorithm, config.keywords.file_type_algorithms());
+    crawl_database.set_corpus_stop_words(
+        config.corpus.stop_word_share,
+        config.corpus.min_documents,
+        config.corpus.stop_words.clone(),
+    );
+    crawl_database
+}
+
+/// Runs the saved search and remembers when, so the next `--new-since-last` starts from here
+fn run_saved_search(config: &config::Config, name: &str, new_since_last: bool, notify: bool, limit: usize, format: OutputFormat) {
+    let index_db = configured_database(config);
+    let Some(saved) = index_db.saved_search(name) else {
+        println!("No saved search named {:?}, see them with: is saved", name);
+        exit(1);
+    };
+
+    // Taken before searching, files indexed meanwhile show up next time rather than never
+    let started = crawl::now_millis();
+    let synonyms = Synonyms::load(&synonyms_location());
+    let options = SearchOptions {
+        synonyms: Some(&synonyms),
+        limit: Some(limit),
+        indexed_since: if new_since_last { saved.last_run } else { None },
+        ..Default::default()
+    };
+    let page = search_through_database(&index_db, saved.query, &config.ranking, &options, format);
+    index_db.mark_search_run(name, started);
+
+    print_results(&page.results, 0, format);
+    if notify && page.total > 0 {
+        let noun = if page.total == 1 { "result" } else { "results" };
+        let adjective = if new_since_last { " new" } else { "" };
+        let summary = format!("{}: {}{} {}", name, page.total, adjective, noun);
+        let body: Vec<&str> = page.results.iter().map(|sr| sr.filename.as_str()).collect();
+        if let Err(e) = Command::new("notify-send").arg(summary).arg(body.join("\n")).status() {
+            eprintln!("[!] Cannot send notification: {}", e);
+        }
+    }
+}
+
 fn main() {
     let args = Cli::parse();
+    let config = config::Config::load(&config_location());
 
+    if let Some(command) = args.command {
+        let index_db = crawl::CrawlDatabase::init(&database_location());
+        match command {
+            Commands::Failures => index_db.list_extraction_failures(),
+            Commands::Dupes => index_db.list_duplicates(),
+            Commands::Save { name, query } => {
+                index_db.save_search(&name, &query.join(" "));
+                println!("Saved, run it with: is run {}", name);
+            }
+            Commands::Saved => index_db.list_saved_searches(),
+            Commands::Run { name, new_since_last, notify, limit, format } => {
+                run_saved_search(&config, &name, new_since_last, notify, limit, format)
+            }
+        }
+        exit(0);
+    }
 
     // Check if crawl parameter was given
-    match args.crawl_dir {
-        Some(crawl_path) => {
-            let mut crawl_database = crawl::CrawlDatabase::init(&database_location());
+    if let Some(crawl_path) = args.crawl_dir {
+        let mut crawl_database = configured_database(&config);
+        if let Some(model_path) = config.semantic.model_path() {
+            match EmbeddingModel::load(&model_path, None) {
+                Ok(model) => crawl_database.set_embedding_model(model),
+                Err(e) => println!("[!] {}, crawling without the semantic index", e),
+            }
+        }
+        if args.full_text {
+            crawl_database.enable_full_text();
+        }
+        if args.no_full_text {
+            crawl_database.disable_full_text();
+        }
 
-            println!("Starting crawl for path [{:?}]", crawl_path);
-            crawl_database.start_crawl(PathBuf::from(crawl_path));
+        println!("Starting crawl for path [{:?}]", crawl_path);
+        crawl_database.start_crawl(PathBuf::from(crawl_path));
 
-            println!("Finished Indexing!");
-            println!("You can now quickly search with: is <TERM>");
-            exit(0);
-        }
-        None => {}
+        println!("Finished Indexing!");
+        println!("You can now quickly search with: is <TERM>");
+        exit(0);
     }
 
     // Otherwise activate search mode
-    match args.search_term {
-        Some(search) => {
-            let index_db = crawl::CrawlDatabase::init(&database_location());
-            search_through_database(&index_db, search);
+    if args.search_term.is_none() && !args.interactive && args.like.is_none() {
+        println!("Provide a search term.");
+        exit(1);
+    }
+    let search = args.search_term.unwrap_or_default();
+    let mut index_db = configured_database(&config);
+
+    // Only the vectors of the query's words are loaded, the interactive mode can't know them ahead
+    let query_words: HashSet<String> = fuzzy::tokens(&search).collect();
+    let only_words = (!args.interactive).then_some(&query_words);
+    let model = match (args.semantic, config.semantic.model_path()) {
+        (true, Some(path)) => EmbeddingModel::load(&path, only_words)
+            .map_err(|e| eprintln!("[!] {}", e))
+            .ok(),
+        (true, None) => {
+            eprintln!("[!] Semantic search needs a model in the [semantic] section of the config");
+            None
         }
-        None => {
-            println!("Provide a search term.");
+        (false, _) => None,
+    };
+    let synonyms = Synonyms::load(&synonyms_location());
+    // Indexed paths are canonical, so the scope has to be as well
+    let scope = match (args.in_dir, args.here) {
+        (Some(dir), _) => Some(expand_home(&dir)),
+        (None, true) => Some(current_dir().unwrap()),
+        (None, false) => None,
+    };
+    let directory = scope.map(|dir| match dir.canonicalize() {
+        Ok(canonical) => canonical.to_str().unwrap().to_string(),
+        Err(e) => {
+            println!("Cannot search in {:?}: {}", dir, e);
+            exit(1);
+        }
+    });
+    let open = args.open.map(|n| n as usize).or((args.first || args.reveal).then_some(1));
+    let (offset, limit) = match (open, args.page) {
+        // Only the opened result needs a snippet
+        (Some(n), _) => (n - 1, 1),
+        // Counting needs no results at all
+        _ if args.count => (0, 0),
+        (None, Some(page)) => ((page as usize - 1).saturating_mul(args.limit), args.limit),
+        (None, None) => (args.offset, args.limit),
+    };
+    let options = SearchOptions {
+        semantic: model.as_ref().map(|model| SemanticSearch { model, weight: config.semantic.weight }),
+        synonyms: Some(&synonyms),
+        limit: Some(limit),
+        offset,
+        sort: args.sort,
+        collapse_duplicates: args.collapse_duplicates,
+        directory,
+        ..Default::default()
+    };
+
+    let openers = Openers::new(config.opener_commands());
+
+    if args.interactive {
+        if let Err(e) = tui::run(&index_db, &search, &config.ranking, options, &openers) {
+            eprintln!("[!] Terminal UI failed: {}", e);
             exit(1);
         }
+        exit(0);
+    }
+
+    let format = if args.print0 { OutputFormat::Null } else { args.format };
+    let page = match &args.like {
+        Some(file) => {
+            let path = expand_home(file).canonicalize().unwrap_or_else(|e| {
+                println!("Cannot read {:?}: {}", file, e);
+                exit(1);
+            });
+            match index_db.document_keywords(&path) {
+                Ok(document) => rank_similar(&index_db, &document.keywords, document.si_id, &options),
+                Err(reason) => {
+                    println!("Cannot extract keywords of {:?} ({})", path, reason.description());
+                    exit(1);
+                }
+            }
+        }
+        None => search_through_database(&index_db, search, &config.ranking, &options, format),
+    };
+    if args.count {
+        println!("{}", page.total);
+        exit(0);
+    }
+    if let Some(n) = open {
+        open_result(&index_db, &page, n, args.reveal, &openers);
+        exit(0);
+    }
+
+    if args.explain {
+        print_explained(&page.results, offset, &config.ranking);
+    } else {
+        print_results(&page.results, offset, format);
+    }
+    let remaining = page.total.saturating_sub(offset + page.results.len());
+    if remaining > 0 && !page.results.is_empty() {
+        let next = if args.page.is_some() && limit > 0 {
+            format!("--page {}", (offset + page.results.len()) / limit + 1)
+        } else {
+            format!("--offset {}", offset + page.results.len())
+        };
+        let noun = if remaining == 1 { "result" } else { "results" };
+        let footer = format!("{} more {}, show them with {}", remaining, noun, next);
+        // Scripts parse stdout, so the footer goes to stderr for them
+        if format.is_machine_readable() {
+            eprintln!("{}", footer);
+        } else {
+            println!("\n{}", footer);
+        }
     }
 }
@@ -0,0 +1,131 @@
+use lingua::Language;
+use rust_stemmers::{Algorithm, Stemmer};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Unicode normalisation that runs before stemming. NFKC turns full width characters and
+/// ligatures like "ﬁ" into their plain forms, then case is folded. With `fold_accents` marks are
+/// stripped as well, so "café" and "cafe" become the same word.
+pub fn fold(text: &str, fold_accents: bool) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.nfkc().flat_map(char::to_lowercase) {
+        match c {
+            'ß' => folded.push_str("ss"),
+            'æ' => folded.push_str("ae"),
+            'œ' => folded.push_str("oe"),
+            c => folded.push(c),
+        }
+    }
+
+    if !fold_accents {
+        return folded;
+    }
+
+    folded
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        // Letters with strokes have no decomposition
+        .map(|c| match c {
+            'ø' => 'o',
+            'ł' => 'l',
+            'đ' => 'd',
+            'ħ' => 'h',
+            c => c,
+        })
+        .nfc()
+        .collect()
+}
+
+/// Forms a query term is compared to filenames in, with and without accent folding so the
+/// setting used while crawling doesn't matter
+pub fn folded_forms(term: &str) -> Vec<String> {
+    let mut forms = vec![fold(term, false)];
+    let accents_folded = fold(term, true);
+    if !forms.contains(&accents_folded) {
+        forms.push(accents_folded);
+    }
+    forms
+}
+
+/// Snowball stemmers exist for a subset of the languages we detect
+fn stemming_algorithm(language: Language) -> Option<Algorithm> {
+    match language {
+        Language::Arabic => Some(Algorithm::Arabic),
+        Language::Danish => Some(Algorithm::Danish),
+        Language::Dutch => Some(Algorithm::Dutch),
+        Language::English => Some(Algorithm::English),
+        Language::Finnish => Some(Algorithm::Finnish),
+        Language::French => Some(Algorithm::French),
+        Language::German => Some(Algorithm::German),
+        Language::Greek => Some(Algorithm::Greek),
+        Language::Hungarian => Some(Algorithm::Hungarian),
+        Language::Italian => Some(Algorithm::Italian),
+        Language::Bokmal | Language::Nynorsk => Some(Algorithm::Norwegian),
+        Language::Portuguese => Some(Algorithm::Portuguese),
+        Language::Romanian => Some(Algorithm::Romanian),
+        Language::Russian => Some(Algorithm::Russian),
+        Language::Spanish => Some(Algorithm::Spanish),
+        Language::Swedish => Some(Algorithm::Swedish),
+        Language::Tamil => Some(Algorithm::Tamil),
+        Language::Turkish => Some(Algorithm::Turkish),
+        _ => None,
+    }
+}
+
+/// The form of a word or phrase that is compared at search time: folded and, where a stemmer
+/// for the language exists, every word reduced to its stem.
+pub fn normalize(phrase: &str, language: Option<Language>, fold_accents: bool) -> String {
+    let stemmer = language.and_then(stemming_algorithm).map(Stemmer::create);
+
+    fold(phrase, fold_accents)
+        .split_whitespace()
+        .map(|word| match &stemmer {
+            Some(s) => s.stem(word).into_owned(),
+            None => word.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// We don't know the language of a query, so it is normalised for every language in the index
+pub fn query_forms(term: &str, languages: &[Language]) -> Vec<String> {
+    let mut forms = vec![];
+    for fold_accents in [false, true] {
+        let languages = languages.iter().map(|l| Some(*l)).chain([None]);
+        for language in languages {
+            let form = normalize(term, language, fold_accents);
+            if !forms.contains(&form) {
+                forms.push(form);
+            }
+        }
+    }
+    forms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_forms_share_a_stem() {
+        assert_eq!(
+            normalize("Invoices", Some(Language::English), true),
+            normalize("invoice", Some(Language::English), true)
+        );
+        assert_eq!(
+            normalize("Rechnungen", Some(Language::German), true),
+            normalize("Rechnung", Some(Language::German), true)
+        );
+        assert_eq!(normalize("Due Dates", None, true), "due dates");
+    }
+
+    #[test]
+    fn unicode_variants_fold_together() {
+        assert_eq!(fold("Café", true), "cafe");
+        assert_eq!(fold("Cafe\u{301}", true), "cafe");
+        assert_eq!(fold("Café", false), "café");
+        assert_eq!(fold("Straße", false), "strasse");
+        assert_eq!(fold("ｆｕｌｌ ｗｉｄｔｈ", true), "full width");
+        assert_eq!(fold("ﬁnance Œuvre", true), "finance oeuvre");
+    }
+}
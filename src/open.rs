@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+use crate::crawl::FileType;
+use crate::search::SearchResult;
+
+const DEFAULT_OPENER: &str = "xdg-open {path}";
+
+/// Commands that open results, per file type from the `[openers]` section of the config and
+/// `xdg-open` for everything else. Templates are split at whitespace and run without a shell,
+/// `{path}`, `{page}` and `{line}` are replaced in every argument:
+///
+/// ```toml
+/// [openers]
+/// pdf = "zathura --page {page} {path}"
+/// rust = "code --goto {path}:{line}"
+/// ```
+#[derive(Debug, Default)]
+pub struct Openers {
+    commands: HashMap<FileType, String>,
+}
+
+impl Openers {
+    pub fn new(commands: HashMap<FileType, String>) -> Self {
+        Self { commands }
+    }
+
+    /// The command opening the result at its snippet, page and line are 1 without one
+    pub fn command(&self, sr: &SearchResult) -> Command {
+        let template = self.commands.get(&sr.filetype).map_or(DEFAULT_OPENER, |c| c.as_str());
+        let line = sr.snippet.as_ref().map_or(1, |s| s.line);
+        let page = sr.snippet.as_ref().map_or(1, |s| s.page);
+        let args = expand_template(template, &sr.filepath, page, line);
+
+        let mut command = Command::new(&args[0]);
+        command.args(&args[1..]);
+        command
+    }
+}
+
+/// Opens the folder containing the file with the default file manager
+pub fn reveal_command(filepath: &str) -> Command {
+    let folder = Path::new(filepath).parent().unwrap_or(Path::new("/"));
+    let mut command = Command::new("xdg-open");
+    command.arg(folder);
+    command
+}
+
+fn expand_template(template: &str, path: &str, page: usize, line: usize) -> Vec<String> {
+    let args: Vec<String> = template
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{path}", path)
+                .replace("{page}", &page.to_string())
+                .replace("{line}", &line.to_string())
+        })
+        .collect();
+
+    // A template without any argument can't run, the default opener still can
+    if args.is_empty() {
+        return expand_template(DEFAULT_OPENER, path, page, line);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_replaced_per_argument() {
+        assert_eq!(
+            expand_template("zathura --page {page}  {path}", "/docs/my report.pdf", 3, 40),
+            vec!["zathura", "--page", "3", "/docs/my report.pdf"]
+        );
+        assert_eq!(expand_template("code --goto {path}:{line}", "/src/main.rs", 1, 12), vec!["code", "--goto", "/src/main.rs:12"]);
+        assert_eq!(expand_template(" ", "/a.txt", 1, 1), vec!["xdg-open", "/a.txt"]);
+    }
+}
@@ -0,0 +1,280 @@
+use std::io::{stdout, IsTerminal, Write};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::search::{ComponentKind, RankingWeights, ScoreComponent, SearchResult};
+
+/// How search results are printed. Everything but `text` is meant for scripts and keeps the
+/// schema of `ResultRecord` stable.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable, with snippets
+    #[default]
+    Text,
+    /// A JSON array of records
+    Json,
+    /// One JSON record per line
+    Jsonl,
+    /// Comma separated with a header row
+    Csv,
+    /// Tab separated with a header row
+    Tsv,
+    /// Only the paths, one per line
+    Paths,
+    /// Only the paths, each terminated by a NUL byte like `find -print0`
+    Null,
+}
+
+impl OutputFormat {
+    pub fn is_machine_readable(&self) -> bool {
+        *self != Self::Text
+    }
+}
+
+/// One search result in the machine readable formats, columns of csv and tsv in this order
+#[derive(Debug, Serialize)]
+pub struct ResultRecord<'a> {
+    /// Position in the results, starting at 1
+    pub rank: usize,
+    pub filename: &'a str,
+    pub filepath: &'a str,
+    /// One of the names `type:` filters accept, like "pdf" or "markdown"
+    pub filetype: &'static str,
+    /// Keyword that contributed most, the query terms when only filename, title or text matched
+    pub matching_keyword: &'a str,
+    pub score: f32,
+    /// Line of the snippet, null without one
+    pub line: Option<usize>,
+    /// Best matching passage without highlighting, null without one
+    pub snippet: Option<&'a str>,
+    /// Paths of files with the same content, empty unless duplicates are collapsed
+    pub duplicates: &'a [String],
+}
+
+const COLUMNS: [&str; 9] =
+    ["rank", "filename", "filepath", "filetype", "matching_keyword", "score", "line", "snippet", "duplicates"];
+
+impl<'a> ResultRecord<'a> {
+    pub fn new(rank: usize, sr: &'a SearchResult) -> Self {
+        Self {
+            rank,
+            filename: &sr.filename,
+            filepath: &sr.filepath,
+            filetype: sr.filetype.name(),
+            matching_keyword: &sr.matching_keyword,
+            score: sr.match_score,
+            line: sr.snippet.as_ref().map(|s| s.line),
+            snippet: sr.snippet.as_ref().map(|s| s.text.as_str()),
+            duplicates: &sr.duplicates,
+        }
+    }
+
+    fn fields(&self) -> [String; 9] {
+        [
+            self.rank.to_string(),
+            self.filename.to_string(),
+            self.filepath.to_string(),
+            self.filetype.to_string(),
+            self.matching_keyword.to_string(),
+            self.score.to_string(),
+            self.line.map(|l| l.to_string()).unwrap_or_default(),
+            self.snippet.unwrap_or_default().to_string(),
+            // One column for any number of paths
+            self.duplicates.join(";"),
+        ]
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// TSV has no quoting, so separators inside fields become spaces
+fn tsv_field(field: &str) -> String {
+    field.replace(['\t', '\n', '\r'], " ")
+}
+
+fn print_text(sr: &SearchResult, highlight: bool) {
+    println!("{} [{} -> {}]", sr.filename, sr.match_score, sr.filepath);
+    // The line number is what `$EDITOR +N` expects
+    if let Some(snippet) = &sr.snippet {
+        println!("    {}: {}", snippet.line, snippet.render(highlight));
+    }
+    for duplicate in &sr.duplicates {
+        println!("    also at {}", duplicate);
+    }
+}
+
+fn keyword_matches(kind: &ComponentKind) -> String {
+    let ComponentKind::Keywords { matches, tf, length_ratio, .. } = kind else { return String::new() };
+    let words: Vec<String> = matches
+        .iter()
+        .map(|m| match m.coverage < 1.0 {
+            true => format!("\"{}\" {:.3} × {:.0}% covered", m.word, m.score, m.coverage * 100.0),
+            false => format!("\"{}\" {:.3}", m.word, m.score),
+        })
+        .collect();
+    format!("tf {:.3} from {}, {:.2}× the average keyword count", tf, words.join(", "), length_ratio)
+}
+
+/// The formula of one component with the weights it used
+fn component_formula(component: &ScoreComponent, weights: &RankingWeights) -> String {
+    let formula = match &component.kind {
+        ComponentKind::Keywords { idf, saturated_tf, .. } => format!(
+            "keywords: keyword_weight {} × idf {:.3} × bm25 tf {:.3}",
+            weights.keyword_weight, idf, saturated_tf
+        ),
+        ComponentKind::FullText { bm25 } => format!("full text: full_text_weight {} × bm25 {:.3}", weights.full_text_weight, bm25),
+        ComponentKind::Filename { idf } => format!("filename: filename_boost {} × idf {:.3}", weights.filename_boost, idf),
+        ComponentKind::Title { idf } => format!("title: title_boost {} × idf {:.3}", weights.title_boost, idf),
+        ComponentKind::Semantic { similarity } => format!(
+            "semantic: weight {:.2} × similarity {:.3}",
+            component.score / similarity,
+            similarity
+        ),
+        ComponentKind::Similarity { shared } => {
+            format!("similarity: cosine of the keyword vectors, sharing {}", shared.join(", "))
+        }
+        ComponentKind::Recency { age_days } => format!(
+            "recency: recency_boost {} × 0.5^({:.1} days old / half-life {})",
+            weights.recency_boost, age_days, weights.recency_half_life_days
+        ),
+        ComponentKind::Frecency { opens, decayed_opens } => format!(
+            "frecency: frecency_boost {} × {:.3} decayed of {} times opened, half-life {}",
+            weights.frecency_boost, decayed_opens, opens, weights.frecency_half_life_days
+        ),
+    };
+    match component.term_weight < 1.0 {
+        true => format!("{} × synonym_weight {}", formula, component.term_weight),
+        false => formula,
+    }
+}
+
+fn print_component(component: &ScoreComponent, weights: &RankingWeights) {
+    match component.term.is_empty() {
+        true => println!("    {:+.4} {}", component.score, component_formula(component, weights)),
+        false => println!("    {:+.4} \"{}\" {}", component.score, component.term, component_formula(component, weights)),
+    }
+    if matches!(component.kind, ComponentKind::Keywords { .. }) {
+        println!("            {}", keyword_matches(&component.kind));
+    }
+}
+
+/// `--explain`: every result with the components its score is made of
+pub fn print_explained(results: &[SearchResult], offset: usize, weights: &RankingWeights) {
+    for (i, sr) in results.iter().enumerate() {
+        println!("{}. {} [{} -> {}]", offset + i + 1, sr.filename, sr.match_score, sr.filepath);
+
+        // Semantic blending scales what the terms matched, not similarity or boosts
+        let (keyword, unscaled): (Vec<&ScoreComponent>, Vec<&ScoreComponent>) =
+            sr.explanation.components.iter().partition(|c| {
+                matches!(
+                    c.kind,
+                    ComponentKind::Keywords { .. }
+                        | ComponentKind::FullText { .. }
+                        | ComponentKind::Filename { .. }
+                        | ComponentKind::Title { .. }
+                )
+            });
+        for component in &keyword {
+            print_component(component, weights);
+        }
+        if let Some(scale) = sr.explanation.keyword_scale {
+            let sum: f32 = keyword.iter().map(|c| c.score).sum();
+            println!("    ×{:.4}  scaled to the best keyword score to blend with similarity, {:.4} in total", scale, sum * scale);
+        }
+        for component in &unscaled {
+            print_component(component, weights);
+        }
+        if sr.explanation.components.is_empty() {
+            println!("    only filters matched, every result scores 0");
+        }
+        println!("    = {}", sr.match_score);
+    }
+}
+
+/// `offset` is the number of better results on earlier pages, ranks continue after them
+pub fn print_results(results: &[SearchResult], offset: usize, format: OutputFormat) {
+    let records: Vec<ResultRecord> = results
+        .iter()
+        .enumerate()
+        .map(|(i, sr)| ResultRecord::new(offset + i + 1, sr))
+        .collect();
+    let mut out = stdout().lock();
+
+    match format {
+        OutputFormat::Text => {
+            let highlight = stdout().is_terminal();
+            for sr in results {
+                print_text(sr, highlight);
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &records).unwrap();
+            writeln!(out).unwrap();
+        }
+        OutputFormat::Jsonl => {
+            for record in &records {
+                serde_json::to_writer(&mut out, record).unwrap();
+                writeln!(out).unwrap();
+            }
+        }
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            let (separator, escape): (&str, fn(&str) -> String) = match format {
+                OutputFormat::Csv => (",", csv_field),
+                _ => ("\t", tsv_field),
+            };
+            writeln!(out, "{}", COLUMNS.join(separator)).unwrap();
+            for record in &records {
+                let fields: Vec<String> = record.fields().iter().map(|f| escape(f)).collect();
+                writeln!(out, "{}", fields.join(separator)).unwrap();
+            }
+        }
+        OutputFormat::Paths => {
+            for record in &records {
+                writeln!(out, "{}", record.filepath).unwrap();
+            }
+        }
+        OutputFormat::Null => {
+            for record in &records {
+                write!(out, "{}\0", record.filepath).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separated_fields_are_escaped() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a, \"b\""), "\"a, \"\"b\"\"\"");
+        assert_eq!(tsv_field("a\tb\nc"), "a b c");
+    }
+
+    #[test]
+    fn duplicates_are_listed_in_every_format() {
+        let duplicates = vec![String::from("/docs/a (1).pdf"), String::from("/backup/a.pdf")];
+        let record = ResultRecord {
+            rank: 1,
+            filename: "a.pdf",
+            filepath: "/docs/a.pdf",
+            filetype: "pdf",
+            matching_keyword: "invoice",
+            score: 1.0,
+            line: None,
+            snippet: None,
+            duplicates: &duplicates,
+        };
+        assert_eq!(record.fields()[8], "/docs/a (1).pdf;/backup/a.pdf");
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["duplicates"], serde_json::json!(["/docs/a (1).pdf", "/backup/a.pdf"]));
+    }
+}
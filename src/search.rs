@@ -1,10 +1,1733 @@
-use crate::crawl::CrawlDatabase;
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-// TODO: we should keep weights of keywords so that multiple keywords have better SINGLE match
+use clap::ValueEnum;
+use lingua::Language;
+use rusqlite::{params_from_iter, Connection, ToSql};
+use serde::Deserialize;
 
-pub fn search_through_database(cdb: &CrawlDatabase, search_term: String) {
-    // Cleaning up search term
-    // search_term.trim().split_whitespace()
+use crate::crawl::{indexed_languages, CrawlDatabase, FileType};
+use crate::dupes::{clusters, Signature};
+use crate::file_index::language_from_name;
+use crate::fuzzy;
+use crate::normalize::{folded_forms, query_forms};
+use crate::output::OutputFormat;
+use crate::segment::{contains_cjk, segment, unsegment};
+use crate::semantic::{from_blob, similarity, EmbeddingModel};
+use crate::snippet::{best_snippet, leading_snippet, Passage, Snippet};
+use crate::synonyms::Synonyms;
 
-    cdb.search_keyword(&search_term);
+pub const RESULT_LIMIT: usize = 20;
+// Vocabulary words sharing the most trigrams with a misspelled term that get a full distance check
+const FUZZY_CANDIDATES: usize = 50;
+const FUZZY_EXPANSIONS: usize = 3;
+// Less similar documents are not semantic matches, however few results there are
+const SEMANTIC_MIN_SIMILARITY: f32 = 0.3;
+// Best keywords of the file given to `--like` that snippets of similar documents highlight
+const SIMILAR_SNIPPET_TERMS: usize = 5;
+
+/// Weights of the ranking model, tunable in the `[ranking]` section of the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RankingWeights {
+    /// BM25 term frequency saturation
+    pub k1: f32,
+    /// BM25 document length normalisation, 0 turns it off
+    pub b: f32,
+    pub keyword_weight: f32,
+    pub full_text_weight: f32,
+    pub filename_boost: f32,
+    pub title_boost: f32,
+    /// Synonyms and acronym expansions count this much of the term they were found for
+    pub synonym_weight: f32,
+    /// Added to files modified just now, halving every `recency_half_life_days`. 0 turns it off.
+    pub recency_boost: f32,
+    pub recency_half_life_days: f32,
+    /// Added for every time a file was opened from results, halving every
+    /// `frecency_half_life_days` after the opening. 0 turns it off.
+    pub frecency_boost: f32,
+    pub frecency_half_life_days: f32,
+}
+
+impl Default for RankingWeights {
+    fn default() -> Self {
+        Self {
+            k1: 1.2,
+            b: 0.75,
+            keyword_weight: 1.0,
+            full_text_weight: 0.5,
+            filename_boost: 2.0,
+            title_boost: 1.5,
+            synonym_weight: 0.5,
+            recency_boost: 0.0,
+            recency_half_life_days: 30.0,
+            frecency_boost: 0.0,
+            frecency_half_life_days: 14.0,
+        }
+    }
+}
+
+/// How a search runs, besides the query itself
+#[derive(Default)]
+pub struct SearchOptions<'a> {
+    /// Blends nearest neighbours of the query's embedding into the ranking
+    pub semantic: Option<SemanticSearch<'a>>,
+    /// Groups of interchangeable terms from the user's synonyms file
+    pub synonyms: Option<&'a Synonyms>,
+    /// Only documents of these types, any type when empty
+    pub filetypes: Vec<FileType>,
+    /// Only documents below this canonical directory
+    pub directory: Option<String>,
+    /// Results to return, `RESULT_LIMIT` without one
+    pub limit: Option<usize>,
+    /// Best results skipped before the returned ones
+    pub offset: usize,
+    pub sort: SortOrder,
+    /// Shows only the best ranked of files with the same or nearly the same content
+    pub collapse_duplicates: bool,
+    /// Only documents indexed after this time, in milliseconds since the epoch
+    pub indexed_since: Option<u64>,
+}
+
+/// Order of the results, ties keep the order by score
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum SortOrder {
+    /// Best match first
+    #[default]
+    Score,
+    /// Most recently modified first
+    Modified,
+    /// By filename, alphabetically
+    Name,
+    /// Largest first
+    Size,
+}
+
+/// The requested page of results and how many documents matched in total
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    pub total: usize,
+}
+
+pub struct SemanticSearch<'a> {
+    pub model: &'a EmbeddingModel,
+    /// 0 ranks by keywords only, 1 by similarity only
+    pub weight: f32,
+}
+
+#[derive(Debug)]
+pub struct SearchResult {
+    pub filename: String,
+    pub filepath: String,
+    pub filetype: FileType,
+    pub matching_keyword: String,
+    pub match_score: f32,
+    pub snippet: Option<Snippet>,
+    pub explanation: Explanation,
+    /// Paths of duplicates collapsed into this result
+    pub duplicates: Vec<String>,
+}
+
+/// How the score of a result was built, printed by `--explain`. The score is the sum of the
+/// components, the non semantic ones multiplied by `keyword_scale` when there is one.
+#[derive(Debug, Clone, Default)]
+pub struct Explanation {
+    pub components: Vec<ScoreComponent>,
+    /// Factor blending keyword scores with semantic similarity, see `score_semantic`
+    pub keyword_scale: Option<f32>,
+}
+
+/// What one query term added to the score through one field
+#[derive(Debug, Clone)]
+pub struct ScoreComponent {
+    pub term: String,
+    /// Below 1 for synonyms and acronym expansions
+    pub term_weight: f32,
+    pub score: f32,
+    pub kind: ComponentKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum ComponentKind {
+    /// BM25 over the keywords, `tf` is the sum of the matching keywords' scores
+    Keywords {
+        matches: Vec<KeywordMatch>,
+        tf: f32,
+        idf: f32,
+        saturated_tf: f32,
+        /// Keywords of the document compared to the average, long documents are penalised
+        length_ratio: f32,
+    },
+    /// BM25 of the full text index
+    FullText { bm25: f32 },
+    Filename { idf: f32 },
+    Title { idf: f32 },
+    Semantic { similarity: f32 },
+    Recency { age_days: f32 },
+    /// `decayed_opens` counts every opening with the half-life applied
+    Frecency { opens: usize, decayed_opens: f32 },
+    /// Cosine similarity of the keyword vectors of two documents, `shared` are the keywords with
+    /// terms of the other document, the ones contributing most first
+    Similarity { shared: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct KeywordMatch {
+    pub word: String,
+    /// As stored while indexing, the configured algorithm's score scaled so the document's best
+    /// keyword has 1.0, weighted by idf
+    pub score: f32,
+    /// Share of the keyword the term covers, partial matches count less
+    pub coverage: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Word(String),
+    Phrase(String),
+}
+
+impl Term {
+    fn text(&self) -> &str {
+        match self {
+            Term::Word(w) => w,
+            Term::Phrase(p) => p,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Before,
+    BeforeOrOn,
+    On,
+    OnOrAfter,
+    After,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterKind {
+    Type(FileType),
+    Ext(String),
+    Path(String),
+    Lang(String),
+    Name(String),
+    // Milliseconds since epoch of the start of the given day, like last_modified_timestamp
+    Modified(Comparison, u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub negated: bool,
+}
+
+/// A parsed search query. Every group has to match (implicit AND), within a group a single
+/// term is enough (OR). Excluded terms must not match at all.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Query {
+    pub groups: Vec<Vec<Term>>,
+    pub excluded: Vec<Term>,
+    pub filters: Vec<Filter>,
+    /// Terms added by synonym expansion with the share of a typed term's score they get
+    pub expansion_weights: HashMap<String, f32>,
+    /// Typed terms that have synonyms, they are not treated as typos
+    pub expanded: HashSet<String>,
+}
+
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Days since 1970-01-01 of a proleptic gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Year, month and day of a number of days since 1970-01-01, the inverse of `days_from_civil`
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// `YYYY-MM-DD` of a timestamp in milliseconds, like our modification times
+pub fn format_date(millis: u64) -> String {
+    let (year, month, day) = civil_from_days((millis / MILLIS_PER_DAY) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Parses `YYYY-MM-DD` into milliseconds since epoch
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) as u64 * MILLIS_PER_DAY)
+}
+
+fn parse_modified(value: &str) -> Option<FilterKind> {
+    let (comparison, date) = if let Some(d) = value.strip_prefix(">=") {
+        (Comparison::OnOrAfter, d)
+    } else if let Some(d) = value.strip_prefix("<=") {
+        (Comparison::BeforeOrOn, d)
+    } else if let Some(d) = value.strip_prefix('>') {
+        (Comparison::After, d)
+    } else if let Some(d) = value.strip_prefix('<') {
+        (Comparison::Before, d)
+    } else {
+        (Comparison::On, value.strip_prefix('=').unwrap_or(value))
+    };
+    Some(FilterKind::Modified(comparison, parse_date(date)?))
+}
+
+fn parse_filter(field: &str, value: &str) -> Option<FilterKind> {
+    if value.is_empty() {
+        return None;
+    }
+    match field {
+        "type" => FileType::from_name(value).map(FilterKind::Type),
+        "ext" => Some(FilterKind::Ext(value.trim_start_matches('.').to_lowercase())),
+        "path" => Some(FilterKind::Path(value.to_string())),
+        "lang" => language_from_name(value).map(|l| FilterKind::Lang(l.to_string())),
+        "name" => Some(FilterKind::Name(value.to_string())),
+        "modified" => parse_modified(value),
+        _ => None,
+    }
+}
+
+/// Splits the raw query into tokens, keeping quoted parts together. The second value tells
+/// whether the token contained quotes, which makes it a phrase instead of a word.
+fn tokenize(raw: &str) -> Vec<(String, bool)> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+
+    for c in raw.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                quoted = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() || quoted {
+                    tokens.push((std::mem::take(&mut current), quoted));
+                }
+                quoted = false;
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() || quoted {
+        tokens.push((current, quoted));
+    }
+    tokens
+}
+
+impl Query {
+    pub fn parse(raw: &str) -> Self {
+        let mut query = Query::default();
+        let mut join_with_previous = false;
+
+        for (token, quoted) in tokenize(raw) {
+            if token == "OR" && !quoted {
+                join_with_previous = !query.groups.is_empty();
+                continue;
+            }
+
+            let (negated, token) = match token.strip_prefix('-') {
+                Some(rest) if !rest.is_empty() => (true, rest.to_string()),
+                _ => (false, token),
+            };
+
+            // Field filters like type:pdf, the value may be quoted
+            if let Some((field, value)) = token.split_once(':') {
+                if let Some(kind) = parse_filter(&field.to_lowercase(), value) {
+                    query.filters.push(Filter { kind, negated });
+                    join_with_previous = false;
+                    continue;
+                }
+            }
+
+            if token.trim().is_empty() {
+                continue;
+            }
+            // Words of CJK queries are not separated, segment them like the indexed text
+            let token = if contains_cjk(&token) {
+                segment(&token).split_whitespace().collect::<Vec<&str>>().join(" ")
+            } else {
+                token
+            };
+            let term = if token.contains(char::is_whitespace) && (quoted || contains_cjk(&token)) {
+                Term::Phrase(token)
+            } else {
+                Term::Word(token)
+            };
+
+            if negated {
+                query.excluded.push(term);
+            } else if join_with_previous {
+                query.groups.last_mut().unwrap().push(term);
+            } else {
+                query.groups.push(vec![term]);
+            }
+            join_with_previous = false;
+        }
+
+        query
+    }
+
+    /// All terms that contribute to the score
+    pub fn positive_terms(&self) -> Vec<&str> {
+        self.groups.iter().flatten().map(|t| t.text()).collect()
+    }
+
+    /// Compiles the query into a parameterised condition on `search_index S`. Terms are
+    /// normalised for each of the given languages.
+    pub fn to_sql(&self, languages: &[Language]) -> (String, Vec<Box<dyn ToSql>>) {
+        let mut conditions: Vec<String> = vec![];
+        let mut params: Vec<Box<dyn ToSql>> = vec![];
+
+        for group in &self.groups {
+            let alternatives: Vec<String> = group
+                .iter()
+                .map(|term| term_condition(term, languages, &mut params))
+                .collect();
+            conditions.push(format!("({})", alternatives.join(" OR ")));
+        }
+
+        for term in &self.excluded {
+            conditions.push(format!("NOT IFNULL({}, 0)", term_condition(term, languages, &mut params)));
+        }
+
+        for filter in &self.filters {
+            let condition = filter_condition(&filter.kind, &mut params);
+            // Columns like language can be NULL, which must not make NOT drop the row
+            if filter.negated {
+                conditions.push(format!("NOT IFNULL({}, 0)", condition));
+            } else {
+                conditions.push(condition);
+            }
+        }
+
+        if conditions.is_empty() {
+            (String::from("1"), params)
+        } else {
+            (conditions.join(" AND "), params)
+        }
+    }
+}
+
+/// Escapes LIKE's wildcards, for patterns used with `ESCAPE '\'`
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// LIKE pattern matching the text anywhere, taken literally
+fn contains_pattern(text: &str) -> String {
+    format!("%{}%", escape_like(text))
+}
+
+/// Condition on `keywords K` matching the term as typed or any of its normalised forms
+fn keyword_condition(text: &str, languages: &[Language], params: &mut Vec<Box<dyn ToSql>>) -> String {
+    params.push(Box::new(contains_pattern(text)));
+    let mut alternatives = vec![format!("K.word LIKE ?{} ESCAPE '\\'", params.len())];
+
+    for form in query_forms(text, languages) {
+        params.push(Box::new(contains_pattern(&form)));
+        alternatives.push(format!("K.normalized LIKE ?{} ESCAPE '\\'", params.len()));
+    }
+    format!("({})", alternatives.join(" OR "))
+}
+
+/// Condition on `search_index S` matching the term in the filename as shown or unicode folded
+fn filename_condition(text: &str, params: &mut Vec<Box<dyn ToSql>>) -> String {
+    params.push(Box::new(contains_pattern(text)));
+    let mut alternatives = vec![format!("S.filename LIKE ?{} ESCAPE '\\'", params.len())];
+
+    for form in folded_forms(text) {
+        params.push(Box::new(contains_pattern(&form)));
+        alternatives.push(format!("S.filename_normalized LIKE ?{} ESCAPE '\\'", params.len()));
+    }
+    format!("({})", alternatives.join(" OR "))
+}
+
+fn term_condition(term: &Term, languages: &[Language], params: &mut Vec<Box<dyn ToSql>>) -> String {
+    let text = term.text();
+    // Filenames and titles are not segmented, CJK terms match them as typed
+    let typed = unsegment(text);
+    params.push(Box::new(contains_pattern(&typed)));
+    let like = params.len();
+    // Terms are always quoted for FTS5, for phrases this means the words must be adjacent
+    params.push(Box::new(format!("\"{}\"", text.replace('"', "\"\""))));
+    let fts = params.len();
+    let filename = filename_condition(&typed, params);
+    let keyword = keyword_condition(text, languages, params);
+
+    format!(
+        "({filename} OR S.title LIKE ?{like} ESCAPE '\\'
+        OR EXISTS (SELECT 1 FROM keywords K WHERE K.si_id=S.id AND {keyword})
+        OR S.id IN (SELECT rowid FROM fulltext WHERE fulltext MATCH ?{fts}))"
+    )
+}
+
+fn filter_condition(kind: &FilterKind, params: &mut Vec<Box<dyn ToSql>>) -> String {
+    match kind {
+        FilterKind::Type(filetype) => {
+            params.push(Box::new(*filetype as i64));
+            format!("S.filetype = ?{}", params.len())
+        }
+        FilterKind::Ext(ext) => {
+            params.push(Box::new(format!("%.{}", escape_like(ext))));
+            format!("S.filename LIKE ?{} ESCAPE '\\'", params.len())
+        }
+        FilterKind::Path(path) => {
+            params.push(Box::new(contains_pattern(path)));
+            format!("S.filepath LIKE ?{} ESCAPE '\\'", params.len())
+        }
+        // Any of the languages of mixed language documents counts
+        FilterKind::Lang(language) => {
+            params.push(Box::new(language.clone()));
+            let name = params.len();
+            params.push(Box::new(format!("%,{},%", escape_like(language))));
+            format!("(S.language = ?{} OR ',' || S.languages || ',' LIKE ?{} ESCAPE '\\')", name, params.len())
+        }
+        FilterKind::Name(name) => {
+            params.push(Box::new(contains_pattern(name)));
+            format!("S.filename LIKE ?{} ESCAPE '\\'", params.len())
+        }
+        FilterKind::Modified(comparison, day_start) => {
+            params.push(Box::new(*day_start as i64));
+            let start = params.len();
+            params.push(Box::new((day_start + MILLIS_PER_DAY) as i64));
+            let end = params.len();
+            match comparison {
+                Comparison::Before => format!("S.last_modified_timestamp < ?{start}"),
+                Comparison::BeforeOrOn => format!("S.last_modified_timestamp < ?{end}"),
+                Comparison::On => format!("(S.last_modified_timestamp >= ?{start} AND S.last_modified_timestamp < ?{end})"),
+                Comparison::OnOrAfter => format!("S.last_modified_timestamp >= ?{start}"),
+                Comparison::After => format!("S.last_modified_timestamp >= ?{end}"),
+            }
+        }
+    }
+}
+
+/// A query term without any match, and the close vocabulary words it was replaced with
+#[derive(Debug)]
+pub struct Correction {
+    pub original: String,
+    pub replacements: Vec<String>,
+}
+
+fn term_has_matches(conn: &Connection, term: &Term, languages: &[Language]) -> bool {
+    let mut params: Vec<Box<dyn ToSql>> = vec![];
+    let condition = term_condition(term, languages, &mut params);
+    conn.query_row(
+        &format!("SELECT EXISTS (SELECT 1 FROM search_index S WHERE {})", condition),
+        params_from_iter(params.iter()),
+        |r| r.get(0),
+    )
+    .unwrap()
+}
+
+/// Vocabulary words within a few edits of the given word, closest and most common first
+fn fuzzy_matches(conn: &Connection, word: &str) -> Vec<String> {
+    let word = word.to_lowercase();
+    let max_distance = fuzzy::max_distance(&word);
+    if max_distance == 0 {
+        return vec![];
+    }
+
+    // Cheap prefilter on shared trigrams before computing edit distances
+    let trigrams: Vec<String> = fuzzy::trigrams(&word).into_iter().collect();
+    let placeholders = vec!["?"; trigrams.len()].join(",");
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT V.word, V.document_count FROM vocabulary_trigrams T INNER JOIN vocabulary V ON T.word=V.word
+            WHERE T.trigram IN ({}) GROUP BY V.word ORDER BY COUNT(*) DESC LIMIT {}",
+            placeholders, FUZZY_CANDIDATES
+        ))
+        .unwrap();
+
+    let mut close: Vec<(usize, i64, String)> = stmt
+        .query_map(params_from_iter(trigrams.iter()), |r| Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)?)))
+        .unwrap()
+        .map(|c| c.unwrap())
+        .map(|(candidate, count)| (fuzzy::levenshtein(&word, &candidate), count, candidate))
+        .filter(|(distance, _, _)| *distance <= max_distance)
+        .collect();
+
+    close.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    close
+        .into_iter()
+        .take(FUZZY_EXPANSIONS)
+        .map(|(_, _, candidate)| candidate)
+        .collect()
+}
+
+impl Query {
+    /// How much a term's matches count, less than 1 for synonyms and acronyms it was expanded with
+    pub fn term_weight(&self, text: &str) -> f32 {
+        self.expansion_weights.get(text).copied().unwrap_or(1.0)
+    }
+
+    /// Adds synonyms from the user's file and acronyms defined in the indexed documents to the
+    /// group of every term. They match like the term itself, but score `weight` times as much.
+    pub fn expand_synonyms(&mut self, conn: &Connection, synonyms: Option<&Synonyms>, weight: f32) {
+        for group in self.groups.iter_mut() {
+            let mut expansions: Vec<String> = vec![];
+            for term in group.iter() {
+                let text = term.text();
+                let mut found = synonyms.map(|s| s.expansions(text)).unwrap_or_default();
+                found.extend(acronym_expansions(conn, text));
+
+                for expansion in found {
+                    let known = group.iter().any(|t| t.text().eq_ignore_ascii_case(&expansion))
+                        || expansions.iter().any(|e| e.eq_ignore_ascii_case(&expansion));
+                    if !known {
+                        self.expanded.insert(text.to_string());
+                        expansions.push(expansion);
+                    }
+                }
+            }
+
+            for expansion in expansions {
+                self.expansion_weights.insert(expansion.clone(), weight);
+                if expansion.contains(' ') {
+                    group.push(Term::Phrase(expansion));
+                } else {
+                    group.push(Term::Word(expansion));
+                }
+            }
+        }
+    }
+
+    /// Replaces words that match nothing with close words from the vocabulary
+    pub fn correct_typos(&mut self, conn: &Connection) -> Vec<Correction> {
+        let mut corrections = vec![];
+        let languages = indexed_languages(conn);
+
+        for group in self.groups.iter_mut() {
+            let mut corrected_group = vec![];
+            for term in group.drain(..) {
+                let replacements = match &term {
+                    Term::Word(word) if !self.expanded.contains(word) && !term_has_matches(conn, &term, &languages) => {
+                        fuzzy_matches(conn, word)
+                    }
+                    _ => vec![],
+                };
+
+                if replacements.is_empty() {
+                    corrected_group.push(term);
+                } else {
+                    corrected_group.extend(replacements.iter().cloned().map(Term::Word));
+                    corrections.push(Correction {
+                        original: term.text().to_string(),
+                        replacements,
+                    });
+                }
+            }
+            *group = corrected_group;
+        }
+
+        corrections
+    }
+}
+
+/// Full names of an acronym and acronyms of a full name, as defined in the indexed documents
+fn acronym_expansions(conn: &Connection, text: &str) -> Vec<String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT expansion FROM acronyms WHERE acronym = ?1 COLLATE NOCASE
+            UNION SELECT acronym FROM acronyms WHERE expansion = ?1 COLLATE NOCASE",
+        )
+        .unwrap();
+    stmt.query_map((text,), |r| r.get(0))
+        .unwrap()
+        .map(|e| e.unwrap())
+        .collect()
+}
+
+/// Ids of all documents that satisfy the boolean structure and filters of the query
+fn matching_documents(conn: &Connection, query: &Query, languages: &[Language]) -> HashSet<i64> {
+    let (condition, params) = query.to_sql(languages);
+    let mut stmt = conn
+        .prepare(&format!("SELECT S.id FROM search_index S WHERE {}", condition))
+        .unwrap();
+
+    stmt.query_map(params_from_iter(params.iter()), |r| r.get(0))
+        .unwrap()
+        .map(|id| id.unwrap())
+        .collect()
+}
+
+/// Unlike `type:` filters, which all have to match, this keeps documents of any of the types
+fn restrict_to_types(conn: &Connection, filetypes: &[FileType], documents: &mut HashSet<i64>) {
+    if filetypes.is_empty() {
+        return;
+    }
+    let types: Vec<String> = filetypes.iter().map(|t| (*t as i64).to_string()).collect();
+    let mut stmt = conn
+        .prepare(&format!("SELECT id FROM search_index WHERE filetype IN ({})", types.join(",")))
+        .unwrap();
+    let of_types: HashSet<i64> = stmt.query_map((), |r| r.get(0)).unwrap().map(|id| id.unwrap()).collect();
+    documents.retain(|id| of_types.contains(id));
+}
+
+/// Keeps the documents below the directory. Paths are stored canonical, so these all sort between
+/// "dir/" and "dir0", '0' following '/', and the unique index on filepath answers the range.
+fn restrict_to_directory(conn: &Connection, directory: Option<&str>, documents: &mut HashSet<i64>) {
+    let Some(directory) = directory else { return };
+    // Everything is below the root
+    if directory.trim_end_matches('/').is_empty() {
+        return;
+    }
+    let prefix = format!("{}/", directory.trim_end_matches('/'));
+    let upper = format!("{}0", &prefix[..prefix.len() - 1]);
+
+    let mut stmt = conn
+        .prepare("SELECT id FROM search_index WHERE filepath >= ?1 AND filepath < ?2")
+        .unwrap();
+    let below: HashSet<i64> = stmt
+        .query_map((&prefix, &upper), |r| r.get(0))
+        .unwrap()
+        .map(|id| id.unwrap())
+        .collect();
+    documents.retain(|id| below.contains(id));
+}
+
+/// Keeps the documents added or changed since the time. Rows from before `indexed_at` existed
+/// have none and count as old.
+fn restrict_to_indexed_since(conn: &Connection, since: Option<u64>, documents: &mut HashSet<i64>) {
+    let Some(since) = since else { return };
+    let mut stmt = conn.prepare("SELECT id FROM search_index WHERE indexed_at > ?1").unwrap();
+    let recent: HashSet<i64> = stmt.query_map((since,), |r| r.get(0)).unwrap().map(|id| id.unwrap()).collect();
+    documents.retain(|id| recent.contains(id));
+}
+
+/// Numbers about the whole index that BM25 needs
+struct CorpusStats {
+    documents: f32,
+    // Keywords are our "words" of a document, so this is the average document length
+    avg_keywords: f32,
+}
+
+/// Score of a document accumulated over all query terms and fields
+#[derive(Default)]
+struct Candidate {
+    score: f32,
+    best_keyword: Option<(String, f32)>,
+    // Most similar passage when found through the semantic index
+    semantic_passage: Option<Passage>,
+    explanation: Explanation,
+}
+
+impl Candidate {
+    /// Adds the scores a single term gave the document
+    fn merge(&mut self, other: Candidate, weight: f32) {
+        self.score += weight * other.score;
+        if let Some((word, score)) = other.best_keyword {
+            self.add_keyword(word, weight * score);
+        }
+        for mut component in other.explanation.components {
+            component.term_weight = weight;
+            component.score *= weight;
+            self.explanation.components.push(component);
+        }
+    }
+
+    fn add_component(&mut self, term: &str, score: f32, kind: ComponentKind) {
+        self.score += score;
+        self.explanation.components.push(ScoreComponent { term: term.to_string(), term_weight: 1.0, score, kind });
+    }
+
+    fn add_keyword(&mut self, word: String, score: f32) {
+        match &self.best_keyword {
+            Some((_, best)) if *best >= score => {}
+            _ => self.best_keyword = Some((word, score)),
+        }
+    }
+}
+
+fn corpus_stats(conn: &Connection) -> CorpusStats {
+    conn.query_row(
+        "SELECT (SELECT COUNT(*) FROM search_index), (SELECT COUNT(*) FROM keywords)",
+        (),
+        |r| {
+            let documents: f64 = r.get(0)?;
+            let keywords: f64 = r.get(1)?;
+            Ok(CorpusStats {
+                documents: documents as f32,
+                avg_keywords: if documents > 0.0 { (keywords / documents) as f32 } else { 0.0 },
+            })
+        },
+    )
+    .unwrap()
+}
+
+/// Inverse document frequency, the BM25 variant that never goes negative
+fn idf(documents: f32, matching_documents: f32) -> f32 {
+    ((documents - matching_documents + 0.5) / (matching_documents + 0.5) + 1.0).ln()
+}
+
+/// Saturated term frequency, normalised by how long the document is compared to the average
+fn bm25_tf(tf: f32, doc_length: f32, avg_length: f32, weights: &RankingWeights) -> f32 {
+    let length_ratio = if avg_length > 0.0 { doc_length / avg_length } else { 1.0 };
+    tf * (weights.k1 + 1.0) / (tf + weights.k1 * (1.0 - weights.b + weights.b * length_ratio))
+}
+
+/// BM25 over the keyword lists, the stored scores of matching keywords act as term frequency.
+/// Partial matches only count with the share of the keyword that the term covers.
+fn score_keywords(
+    conn: &Connection,
+    term: &str,
+    languages: &[Language],
+    stats: &CorpusStats,
+    weights: &RankingWeights,
+    candidates: &mut HashMap<i64, Candidate>,
+) {
+    let mut params: Vec<Box<dyn ToSql>> = vec![Box::new(term.to_string())];
+    let condition = keyword_condition(term, languages, &mut params);
+
+    // Bare columns next to MAX() come from the row with the highest score
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT K.si_id, SUM(K.score * MIN(1.0, CAST(LENGTH(?1) AS FLOAT) / LENGTH(K.word))), K.word, MAX(K.score),
+            (SELECT COUNT(*) FROM keywords D WHERE D.si_id=K.si_id),
+            json_group_array(json_object('word', K.word, 'score', K.score,
+                'coverage', MIN(1.0, CAST(LENGTH(?1) AS FLOAT) / LENGTH(K.word))))
+            FROM keywords K WHERE {} GROUP BY K.si_id",
+            condition
+        ))
+        .unwrap();
+
+    let matches: Vec<(i64, f32, String, f32, f32, String)> = stmt
+        .query_map(params_from_iter(params.iter()), |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get::<_, f64>(4)? as f32, r.get(5)?))
+        })
+        .unwrap()
+        .map(|m| m.unwrap())
+        .collect();
+
+    let term_idf = idf(stats.documents, matches.len() as f32);
+    for (si_id, tf, word, word_score, doc_length, keyword_matches) in matches {
+        let candidate = candidates.entry(si_id).or_default();
+        let saturated_tf = bm25_tf(tf, doc_length, stats.avg_keywords, weights);
+        let kind = ComponentKind::Keywords {
+            matches: serde_json::from_str(&keyword_matches).unwrap(),
+            tf,
+            idf: term_idf,
+            saturated_tf,
+            length_ratio: if stats.avg_keywords > 0.0 { doc_length / stats.avg_keywords } else { 1.0 },
+        };
+        candidate.add_component(term, weights.keyword_weight * term_idf * saturated_tf, kind);
+        candidate.add_keyword(word, word_score);
+    }
+}
+
+/// FTS5 already ranks with BM25 over the full text, we only weight it
+fn score_full_text(
+    conn: &Connection,
+    term: &str,
+    weights: &RankingWeights,
+    candidates: &mut HashMap<i64, Candidate>,
+) {
+    let mut stmt = conn
+        .prepare("SELECT rowid, -bm25(fulltext) FROM fulltext WHERE fulltext MATCH :search")
+        .unwrap();
+
+    // Quote the term so FTS5 does not interpret it as query syntax
+    let quoted = format!("\"{}\"", term.replace('"', "\"\""));
+    let matches = stmt
+        .query_map(&[(":search", &quoted)], |r| {
+            Ok((r.get::<_, i64>(0)?, r.get::<_, f64>(1)? as f32))
+        })
+        .unwrap();
+
+    for m in matches {
+        let (si_id, bm25) = m.unwrap();
+        let candidate = candidates.entry(si_id).or_default();
+        candidate.add_component(term, weights.full_text_weight * bm25, ComponentKind::FullText { bm25 });
+    }
+}
+
+/// Filename and title matches are rare and precise, so they get a boost scaled by their own idf
+fn score_fields(
+    conn: &Connection,
+    term: &str,
+    stats: &CorpusStats,
+    weights: &RankingWeights,
+    candidates: &mut HashMap<i64, Candidate>,
+) {
+    let typed = unsegment(term);
+    let mut params: Vec<Box<dyn ToSql>> = vec![Box::new(contains_pattern(&typed))];
+    let filename = filename_condition(&typed, &mut params);
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT S.id, IFNULL({filename}, 0), IFNULL(S.title LIKE ?1 ESCAPE '\\', 0) FROM search_index S
+            WHERE {filename} OR S.title LIKE ?1 ESCAPE '\\'"
+        ))
+        .unwrap();
+
+    let matches: Vec<(i64, bool, bool)> = stmt
+        .query_map(params_from_iter(params.iter()), |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+        })
+        .unwrap()
+        .map(|m| m.unwrap())
+        .collect();
+
+    let filename_idf = idf(stats.documents, matches.iter().filter(|m| m.1).count() as f32);
+    let title_idf = idf(stats.documents, matches.iter().filter(|m| m.2).count() as f32);
+
+    for (si_id, in_filename, in_title) in matches {
+        let candidate = candidates.entry(si_id).or_default();
+        if in_filename {
+            let kind = ComponentKind::Filename { idf: filename_idf };
+            candidate.add_component(term, weights.filename_boost * filename_idf, kind);
+        }
+        if in_title {
+            candidate.add_component(term, weights.title_boost * title_idf, ComponentKind::Title { idf: title_idf });
+        }
+    }
+}
+
+/// Best similarity of any vector of a document, and its most similar passage
+struct Nearest {
+    similarity: f32,
+    passage_similarity: f32,
+    passage: Option<Passage>,
+}
+
+/// Nearest neighbours of the query among all keyword and passage vectors. Keyword scores are
+/// scaled to the best one so they blend with similarities, which are at most 1.
+fn score_semantic(
+    conn: &Connection,
+    terms: &[&str],
+    semantic: &SemanticSearch,
+    passing_filters: &HashSet<i64>,
+    allowed: &mut HashSet<i64>,
+    candidates: &mut HashMap<i64, Candidate>,
+) {
+    let Some(query_vector) = semantic.model.embed(&terms.join(" ")) else { return };
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT E.si_id, E.vector, P.start_byte, P.end_byte, P.line, P.page, P.content FROM embeddings E
+            LEFT JOIN passages P ON P.id = E.passage_id",
+        )
+        .unwrap();
+    let rows = stmt
+        .query_map((), |r| {
+            let passage = match r.get::<_, Option<String>>(6)? {
+                Some(text) => Some(Passage { start: r.get(2)?, end: r.get(3)?, line: r.get(4)?, page: r.get(5)?, text }),
+                None => None,
+            };
+            Ok((r.get::<_, i64>(0)?, r.get::<_, Vec<u8>>(1)?, passage))
+        })
+        .unwrap()
+        .map(|r| r.unwrap());
+
+    let mut nearest: HashMap<i64, Nearest> = HashMap::new();
+    for (si_id, blob, passage) in rows {
+        if !passing_filters.contains(&si_id) {
+            continue;
+        }
+        let s = similarity(&query_vector, &from_blob(&blob));
+        let best = nearest.entry(si_id).or_insert(Nearest {
+            similarity: f32::MIN,
+            passage_similarity: f32::MIN,
+            passage: None,
+        });
+        best.similarity = best.similarity.max(s);
+        if let Some(passage) = passage {
+            if s > best.passage_similarity {
+                best.passage_similarity = s;
+                best.passage = Some(passage);
+            }
+        }
+    }
+
+    // Only documents that can be shown, excluded ones must not change how the others blend
+    let best_keyword_score = candidates
+        .iter()
+        .filter(|(si_id, _)| allowed.contains(si_id))
+        .map(|(_, c)| c.score)
+        .fold(0.0, f32::max);
+    for candidate in candidates.values_mut() {
+        if best_keyword_score > 0.0 {
+            let scale = (1.0 - semantic.weight) / best_keyword_score;
+            candidate.score *= scale;
+            candidate.explanation.keyword_scale = Some(scale);
+        }
+    }
+    for (si_id, n) in nearest {
+        if n.similarity < SEMANTIC_MIN_SIMILARITY {
+            continue;
+        }
+        allowed.insert(si_id);
+        let candidate = candidates.entry(si_id).or_default();
+        let kind = ComponentKind::Semantic { similarity: n.similarity };
+        candidate.add_component(&terms.join(" "), semantic.weight * n.similarity, kind);
+        candidate.semantic_passage = n.passage;
+    }
+}
+
+/// 1 for now, halving every half-life
+fn decay(age_days: f32, half_life_days: f32) -> f32 {
+    if half_life_days <= 0.0 {
+        return 0.0;
+    }
+    0.5_f32.powf(age_days.max(0.0) / half_life_days)
+}
+
+/// What results are boosted and sorted by
+struct Metadata {
+    filename: String,
+    modified: Option<u64>,
+    size: Option<u64>,
+}
+
+/// Metadata of all the documents in one query
+fn load_metadata(conn: &Connection, si_ids: impl Iterator<Item = i64>) -> HashMap<i64, Metadata> {
+    let ids = serde_json::to_string(&si_ids.collect::<Vec<i64>>()).unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, filename, last_modified_timestamp, filesize FROM search_index
+            WHERE id IN (SELECT value FROM json_each(?1))",
+        )
+        .unwrap();
+    stmt.query_map((ids,), |r| {
+        Ok((r.get(0)?, Metadata { filename: r.get(1)?, modified: r.get(2)?, size: r.get(3)? }))
+    })
+    .unwrap()
+    .map(|m| m.unwrap())
+    .collect()
+}
+
+/// Boosts documents modified recently and ones opened from results before
+fn score_recency(conn: &Connection, weights: &RankingWeights, now: u64, candidates: &mut HashMap<i64, Candidate>) {
+    let age_days = |millis: u64| now.saturating_sub(millis) as f32 / MILLIS_PER_DAY as f32;
+
+    if weights.recency_boost > 0.0 {
+        let metadata = load_metadata(conn, candidates.keys().copied());
+        for (si_id, candidate) in candidates.iter_mut() {
+            let Some(modified) = metadata[si_id].modified else { continue };
+            let age_days = age_days(modified);
+            let score = weights.recency_boost * decay(age_days, weights.recency_half_life_days);
+            candidate.add_component("", score, ComponentKind::Recency { age_days });
+        }
+    }
+
+    if weights.frecency_boost > 0.0 {
+        let mut stmt = conn.prepare("SELECT si_id, opened_at FROM open_history").unwrap();
+        let mut opens: HashMap<i64, (usize, f32)> = HashMap::new();
+        for row in stmt.query_map((), |r| Ok((r.get::<_, i64>(0)?, r.get::<_, u64>(1)?))).unwrap() {
+            let (si_id, opened_at) = row.unwrap();
+            let entry = opens.entry(si_id).or_default();
+            entry.0 += 1;
+            entry.1 += decay(age_days(opened_at), weights.frecency_half_life_days);
+        }
+        for (si_id, (opens, decayed_opens)) in opens {
+            if let Some(candidate) = candidates.get_mut(&si_id) {
+                let kind = ComponentKind::Frecency { opens, decayed_opens };
+                candidate.add_component("", weights.frecency_boost * decayed_opens, kind);
+            }
+        }
+    }
+}
+
+/// Reorders ranked documents by their metadata, the sort is stable so ties stay ranked by score
+fn sort_ranked(conn: &Connection, order: SortOrder, ranked: &mut [(i64, Candidate)]) {
+    if order == SortOrder::Score {
+        return;
+    }
+    let metadata = load_metadata(conn, ranked.iter().map(|(si_id, _)| *si_id));
+
+    // Reverse puts files without a modification time or size last
+    match order {
+        SortOrder::Modified => ranked.sort_by_key(|(si_id, _)| std::cmp::Reverse(metadata[si_id].modified)),
+        SortOrder::Name => ranked.sort_by_cached_key(|(si_id, _)| metadata[si_id].filename.to_lowercase()),
+        SortOrder::Size => ranked.sort_by_key(|(si_id, _)| std::cmp::Reverse(metadata[si_id].size)),
+        SortOrder::Score => {}
+    }
+}
+
+/// Ranks all indexed documents matching the query and returns the best ones
+pub fn rank(cdb: &CrawlDatabase, query: &Query, weights: &RankingWeights, options: &SearchOptions) -> Vec<SearchResult> {
+    rank_page(cdb, query, weights, options).results
+}
+
+/// Like `rank`, counting every match but only loading snippets for the page of results
+pub fn rank_page(cdb: &CrawlDatabase, query: &Query, weights: &RankingWeights, options: &SearchOptions) -> SearchPage {
+    let conn = cdb.connection();
+    let stats = corpus_stats(conn);
+    let terms = query.positive_terms();
+    let languages = indexed_languages(conn);
+    let mut allowed = matching_documents(conn, query, &languages);
+    restrict_to_types(conn, &options.filetypes, &mut allowed);
+    restrict_to_directory(conn, options.directory.as_deref(), &mut allowed);
+    restrict_to_indexed_since(conn, options.indexed_since, &mut allowed);
+
+    let mut candidates: HashMap<i64, Candidate> = HashMap::new();
+    for term in &terms {
+        let mut term_candidates: HashMap<i64, Candidate> = HashMap::new();
+        score_keywords(conn, term, &languages, &stats, weights, &mut term_candidates);
+        score_full_text(conn, term, weights, &mut term_candidates);
+        score_fields(conn, term, &stats, weights, &mut term_candidates);
+
+        let weight = query.term_weight(term);
+        for (si_id, candidate) in term_candidates {
+            candidates.entry(si_id).or_default().merge(candidate, weight);
+        }
+    }
+    if let Some(semantic) = &options.semantic {
+        // Semantic matches need not contain the terms, but still have to pass exclusions and filters
+        let without_terms = Query { groups: vec![], ..query.clone() };
+        let mut passing_filters = matching_documents(conn, &without_terms, &languages);
+        restrict_to_types(conn, &options.filetypes, &mut passing_filters);
+        restrict_to_directory(conn, options.directory.as_deref(), &mut passing_filters);
+        restrict_to_indexed_since(conn, options.indexed_since, &mut passing_filters);
+        score_semantic(conn, &terms, semantic, &passing_filters, &mut allowed, &mut candidates);
+    }
+    candidates.retain(|si_id, _| allowed.contains(si_id));
+
+    // A query made only of filters lists everything that passes them
+    if terms.is_empty() {
+        for si_id in allowed {
+            candidates.entry(si_id).or_default();
+        }
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    score_recency(conn, weights, now, &mut candidates);
+
+    page_of_results(conn, candidates, &terms, &languages, options)
+}
+
+/// Keywords of a document as a vector of their terms, like document frequencies count them
+#[derive(Default)]
+struct KeywordVector {
+    terms: HashMap<String, f32>,
+    // (word, normalized form, score)
+    keywords: Vec<(String, String, f32)>,
+}
+
+impl KeywordVector {
+    fn add(&mut self, word: String, normalized: String, score: f32) {
+        for term in normalized.split_whitespace() {
+            *self.terms.entry(term.to_string()).or_default() += score;
+        }
+        self.keywords.push((word, normalized, score));
+    }
+
+    fn norm(&self) -> f32 {
+        self.terms.values().map(|s| s * s).sum::<f32>().sqrt()
+    }
+
+    /// Cosine similarity to the other vector and the keywords sharing terms with it, the ones
+    /// contributing most first. None without any shared term.
+    fn similarity(&self, other: &KeywordVector, other_norm: f32) -> Option<(f32, Vec<(String, f32)>)> {
+        let dot: f32 = self.terms.iter().filter_map(|(t, s)| other.terms.get(t).map(|o| s * o)).sum();
+        let norm = self.norm();
+        if dot <= 0.0 || norm == 0.0 || other_norm == 0.0 {
+            return None;
+        }
+
+        let mut shared: Vec<(String, f32)> = self
+            .keywords
+            .iter()
+            .filter_map(|(word, normalized, score)| {
+                let contribution: f32 = normalized.split_whitespace().filter_map(|t| other.terms.get(t)).map(|o| score * o).sum();
+                (contribution > 0.0).then(|| (word.clone(), contribution))
+            })
+            .collect();
+        shared.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Some((dot / (norm * other_norm), shared))
+    }
+}
+
+/// Documents sharing keyword terms with the given keywords, ranked by the cosine similarity of
+/// their keyword vectors. `keywords` are (normalized form, score) like in `DocumentKeywords`,
+/// `exclude` is the document they belong to.
+pub fn rank_similar(cdb: &CrawlDatabase, keywords: &[(String, f32)], exclude: Option<i64>, options: &SearchOptions) -> SearchPage {
+    let conn = cdb.connection();
+    let mut source = KeywordVector::default();
+    for (normalized, score) in keywords {
+        source.add(normalized.clone(), normalized.clone(), *score);
+    }
+    let source_norm = source.norm();
+
+    // Every document's norm needs all of its keywords, not just the shared ones
+    let mut stmt = conn
+        .prepare("SELECT si_id, word, COALESCE(normalized, LOWER(word)), score FROM keywords ORDER BY si_id")
+        .unwrap();
+    let rows = stmt
+        .query_map((), |r| {
+            Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?, r.get::<_, f64>(3)? as f32))
+        })
+        .unwrap()
+        .map(|r| r.unwrap());
+
+    let mut candidates: HashMap<i64, Candidate> = HashMap::new();
+    let mut compare = |si_id: i64, vector: KeywordVector| {
+        if Some(si_id) == exclude {
+            return;
+        }
+        let Some((similarity, shared)) = vector.similarity(&source, source_norm) else { return };
+        let candidate = candidates.entry(si_id).or_default();
+        candidate.add_keyword(shared[0].0.clone(), shared[0].1);
+        let kind = ComponentKind::Similarity { shared: shared.into_iter().map(|(word, _)| word).collect() };
+        candidate.add_component("", similarity, kind);
+    };
+    // Rows come ordered by document, so only one vector is held at a time
+    let mut current: Option<(i64, KeywordVector)> = None;
+    for (si_id, word, normalized, score) in rows {
+        if current.as_ref().is_some_and(|(id, _)| *id != si_id) {
+            let (id, vector) = current.take().unwrap();
+            compare(id, vector);
+        }
+        current.get_or_insert_with(|| (si_id, KeywordVector::default())).1.add(word, normalized, score);
+    }
+    if let Some((id, vector)) = current {
+        compare(id, vector);
+    }
+
+    let mut allowed: HashSet<i64> = candidates.keys().copied().collect();
+    restrict_to_types(conn, &options.filetypes, &mut allowed);
+    restrict_to_directory(conn, options.directory.as_deref(), &mut allowed);
+    restrict_to_indexed_since(conn, options.indexed_since, &mut allowed);
+    candidates.retain(|si_id, _| allowed.contains(si_id));
+
+    let mut best: Vec<(&String, &f32)> = source.terms.iter().collect();
+    best.sort_by(|a, b| b.1.total_cmp(a.1));
+    let terms: Vec<&str> = best.iter().take(SIMILAR_SNIPPET_TERMS).map(|(term, _)| term.as_str()).collect();
+    page_of_results(conn, candidates, &terms, &indexed_languages(conn), options)
+}
+
+/// Removes duplicates of better ranked documents and returns their paths by the document kept
+fn collapse_duplicates(conn: &Connection, ranked: &mut Vec<(i64, Candidate)>) -> HashMap<i64, Vec<String>> {
+    let ids = serde_json::to_string(&ranked.iter().map(|(si_id, _)| *si_id).collect::<Vec<i64>>()).unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, content_hash, simhash, filepath FROM search_index
+            WHERE id IN (SELECT value FROM json_each(?1))",
+        )
+        .unwrap();
+    let mut rows: HashMap<i64, (Signature, String)> = stmt
+        .query_map((ids,), |r| {
+            let si_id = r.get(0)?;
+            let simhash = r.get::<_, Option<i64>>(2)?.map(|s| s as u64);
+            Ok((si_id, (Signature { si_id, content_hash: r.get(1)?, simhash }, r.get(3)?)))
+        })
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    let (signatures, mut paths): (Vec<Signature>, HashMap<i64, String>) = ranked
+        .iter()
+        .filter_map(|(si_id, _)| rows.remove(si_id))
+        .map(|(signature, path)| {
+            let si_id = signature.si_id;
+            (signature, (si_id, path))
+        })
+        .unzip();
+
+    let mut collapsed: HashSet<i64> = HashSet::new();
+    let mut duplicates: HashMap<i64, Vec<String>> = HashMap::new();
+    // Clusters keep the ranked order, so the first member is the best ranked one
+    for cluster in clusters(&signatures) {
+        let members = &cluster.members[1..];
+        collapsed.extend(members);
+        duplicates.insert(cluster.members[0], members.iter().filter_map(|si_id| paths.remove(si_id)).collect());
+    }
+    ranked.retain(|(si_id, _)| !collapsed.contains(si_id));
+    duplicates
+}
+
+/// Sorts the candidates and loads the results of the requested page with their snippets
+fn page_of_results(
+    conn: &Connection,
+    candidates: HashMap<i64, Candidate>,
+    terms: &[&str],
+    languages: &[Language],
+    options: &SearchOptions,
+) -> SearchPage {
+    let mut ranked: Vec<(i64, Candidate)> = candidates.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.score.total_cmp(&a.1.score).then(a.0.cmp(&b.0)));
+    // Before sorting by metadata, so each group keeps its best scored document
+    let mut duplicates = match options.collapse_duplicates {
+        true => collapse_duplicates(conn, &mut ranked),
+        false => HashMap::new(),
+    };
+    sort_ranked(conn, options.sort, &mut ranked);
+    let total = ranked.len();
+    let limit = options.limit.unwrap_or(RESULT_LIMIT);
+
+    let mut stmt = conn
+        .prepare("SELECT filename, filepath, filetype FROM search_index WHERE id=?1")
+        .unwrap();
+    let mut passages_stmt = conn
+        .prepare("SELECT start_byte, end_byte, line, page, content FROM passages WHERE si_id=?1 ORDER BY start_byte")
+        .unwrap();
+
+    let results = ranked
+        .into_iter()
+        .skip(options.offset)
+        .take(limit)
+        .map(|(si_id, candidate)| {
+            let passages: Vec<Passage> = passages_stmt
+                .query_map((si_id,), |r| {
+                    Ok(Passage { start: r.get(0)?, end: r.get(1)?, line: r.get(2)?, page: r.get(3)?, text: r.get(4)? })
+                })
+                .unwrap()
+                .map(|p| p.unwrap())
+                .collect();
+            // Semantic matches may share no word with the query, their closest passage is shown
+            let snippet = best_snippet(&passages, terms, languages).or_else(|| {
+                candidate.semantic_passage.as_ref().map(leading_snippet)
+            });
+
+            stmt.query_row((si_id,), |r| {
+                Ok(SearchResult {
+                    filename: r.get(0)?,
+                    filepath: r.get(1)?,
+                    filetype: r.get(2)?,
+                    matching_keyword: candidate
+                        .best_keyword
+                        .map(|(word, _)| word)
+                        .unwrap_or_else(|| terms.join(" ")),
+                    match_score: candidate.score,
+                    snippet,
+                    explanation: candidate.explanation,
+                    duplicates: duplicates.remove(&si_id).unwrap_or_default(),
+                })
+            })
+            .unwrap()
+        })
+        .collect();
+
+    SearchPage { results, total }
+}
+
+pub fn search_through_database(
+    cdb: &CrawlDatabase,
+    search_term: String,
+    weights: &RankingWeights,
+    options: &SearchOptions,
+    format: OutputFormat,
+) -> SearchPage {
+    let mut query = Query::parse(&search_term);
+    query.expand_synonyms(cdb.connection(), options.synonyms, weights.synonym_weight);
+
+    for correction in query.correct_typos(cdb.connection()) {
+        let message = format!(
+            "No matches for \"{}\", did you mean \"{}\"?",
+            correction.original, correction.replacements[0]
+        );
+        // Scripts parse stdout, hints go to stderr for them
+        if format.is_machine_readable() {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    rank_page(cdb, &query, weights, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert_document(cdb: &CrawlDatabase, filename: &str, keywords: &[(&str, f32)]) {
+        let conn = cdb.connection();
+        conn.execute(
+            "INSERT INTO search_index (filename, filetype, filepath, last_modified_timestamp) VALUES (?1, 12, ?2, 0)",
+            (filename, format!("/test/{}", filename)),
+        )
+        .unwrap();
+        let si_id = conn.last_insert_rowid();
+        for (word, score) in keywords {
+            conn.execute(
+                "INSERT INTO keywords (si_id, word, score) VALUES (?1, ?2, ?3)",
+                (si_id, word, score),
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn short_precise_document_beats_long_partial_one() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(
+            &cdb,
+            "precise.txt",
+            &[("invoice", 1.0), ("payment", 0.5), ("due date", 0.4), ("customer", 0.3), ("total", 0.2)],
+        );
+        let long: Vec<(String, f32)> = (0..20)
+            .map(|i| match i % 2 {
+                0 => (format!("invoice item {}", i), 0.3),
+                _ => (format!("filler {}", i), 0.3),
+            })
+            .collect();
+        let long: Vec<(&str, f32)> = long.iter().map(|(w, s)| (w.as_str(), *s)).collect();
+        insert_document(&cdb, "long.txt", &long);
+        insert_document(&cdb, "other.txt", &[("holiday", 1.0)]);
+
+        let results = rank(&cdb, &Query::parse("invoice"), &RankingWeights::default(), &SearchOptions::default());
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].filename, "precise.txt");
+    }
+
+    #[test]
+    fn filename_match_counts_without_keywords() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "budget_2024.txt", &[("holiday", 1.0)]);
+        insert_document(&cdb, "notes.txt", &[("holiday", 1.0)]);
+
+        let results = rank(&cdb, &Query::parse("budget"), &RankingWeights::default(), &SearchOptions::default());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].filename, "budget_2024.txt");
+    }
+
+    #[test]
+    fn explanation_adds_up_to_the_score() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "invoice_march.txt", &[("invoice", 1.0), ("invoice list", 0.5), ("payment", 0.3)]);
+        insert_document(&cdb, "other.txt", &[("holiday", 1.0)]);
+
+        let results = rank(&cdb, &Query::parse("invoice"), &RankingWeights::default(), &SearchOptions::default());
+        let explanation = &results[0].explanation;
+        let sum: f32 = explanation.components.iter().map(|c| c.score).sum();
+        assert!((sum - results[0].match_score).abs() < 1e-5);
+
+        let ComponentKind::Keywords { matches, .. } = &explanation.components[0].kind else { panic!() };
+        let words: Vec<&str> = matches.iter().map(|m| m.word.as_str()).collect();
+        assert_eq!(words.len(), 2);
+        assert!(words.contains(&"invoice") && words.contains(&"invoice list"));
+        assert!(matches!(explanation.components[1].kind, ComponentKind::Filename { .. }));
+    }
+
+    #[test]
+    fn recent_and_opened_files_are_boosted() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "old.txt", &[("invoice", 1.0)]);
+        insert_document(&cdb, "new.txt", &[("invoice", 1.0)]);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        cdb.connection()
+            .execute("UPDATE search_index SET last_modified_timestamp=?1, filesize=10 WHERE filename='new.txt'", (now,))
+            .unwrap();
+        let query = Query::parse("invoice");
+        let filenames = |weights: &RankingWeights, sort: SortOrder| -> Vec<String> {
+            let options = SearchOptions { sort, ..Default::default() };
+            rank(&cdb, &query, weights, &options).into_iter().map(|r| r.filename).collect()
+        };
+
+        assert_eq!(filenames(&RankingWeights::default(), SortOrder::Score), vec!["old.txt", "new.txt"]);
+        let recency = RankingWeights { recency_boost: 1.0, ..Default::default() };
+        assert_eq!(filenames(&recency, SortOrder::Score), vec!["new.txt", "old.txt"]);
+
+        cdb.record_open("/test/old.txt");
+        cdb.record_open("/test/old.txt");
+        let frecency = RankingWeights { frecency_boost: 1.0, ..recency };
+        assert_eq!(filenames(&frecency, SortOrder::Score), vec!["old.txt", "new.txt"]);
+
+        assert_eq!(filenames(&frecency, SortOrder::Size), vec!["new.txt", "old.txt"]);
+        assert_eq!(filenames(&recency, SortOrder::Name), vec!["new.txt", "old.txt"]);
+    }
+
+    #[test]
+    fn directory_scope_matches_whole_path_components() {
+        let cdb = CrawlDatabase::init(":memory:");
+        for path in ["work/project/a.txt", "work/project/sub/b.txt", "work/project2/c.txt", "work/projects.txt"] {
+            insert_document(&cdb, path, &[("invoice", 1.0)]);
+        }
+        let filenames = |directory: &str| -> Vec<String> {
+            let options = SearchOptions { directory: Some(directory.to_string()), ..Default::default() };
+            let mut names: Vec<String> =
+                rank(&cdb, &Query::parse("invoice"), &RankingWeights::default(), &options).into_iter().map(|r| r.filename).collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(filenames("/test/work/project"), vec!["work/project/a.txt", "work/project/sub/b.txt"]);
+        assert_eq!(filenames("/test/work/project/"), vec!["work/project/a.txt", "work/project/sub/b.txt"]);
+        assert_eq!(filenames("/").len(), 4);
+    }
+
+    #[test]
+    fn similar_documents_rank_by_keyword_cosine() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "source.txt", &[("invoice", 1.0), ("payment", 0.8), ("customer", 0.2)]);
+        insert_document(&cdb, "close.txt", &[("invoice", 0.9), ("payment", 0.9)]);
+        insert_document(&cdb, "distant.txt", &[("customer", 1.0), ("holiday", 1.0), ("beach", 1.0)]);
+        insert_document(&cdb, "unrelated.txt", &[("holiday", 1.0)]);
+
+        let keywords: Vec<(String, f32)> =
+            vec![(String::from("invoice"), 1.0), (String::from("payment"), 0.8), (String::from("customer"), 0.2)];
+        let page = rank_similar(&cdb, &keywords, Some(1), &SearchOptions::default());
+        let filenames: Vec<&str> = page.results.iter().map(|r| r.filename.as_str()).collect();
+        assert_eq!(filenames, vec!["close.txt", "distant.txt"]);
+        assert!(page.results[0].match_score > 0.9 && page.results[0].match_score <= 1.0);
+        assert_eq!(page.results[0].matching_keyword, "invoice");
+    }
+
+    #[test]
+    fn numeric_keywords_are_compared() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "source.txt", &[("2024", 1.0), ("budget", 0.5)]);
+        insert_document(&cdb, "close.txt", &[("2024", 1.0)]);
+
+        let keywords: Vec<(String, f32)> = vec![(String::from("2024"), 1.0), (String::from("budget"), 0.5)];
+        let page = rank_similar(&cdb, &keywords, Some(1), &SearchOptions::default());
+        assert_eq!(page.results[0].filename, "close.txt");
+        assert_eq!(page.results[0].matching_keyword, "2024");
+    }
+
+    #[test]
+    fn duplicates_collapse_into_best_result() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "report_final.pdf", &[("invoice", 1.0)]);
+        insert_document(&cdb, "report_final (1).pdf", &[("invoice", 0.5)]);
+        insert_document(&cdb, "other.pdf", &[("invoice", 0.8)]);
+        cdb.connection()
+            .execute("UPDATE search_index SET content_hash='abc' WHERE filename LIKE 'report_final%'", ())
+            .unwrap();
+        let query = Query::parse("invoice");
+
+        assert_eq!(rank_page(&cdb, &query, &RankingWeights::default(), &SearchOptions::default()).total, 3);
+        let options = SearchOptions { collapse_duplicates: true, ..Default::default() };
+        let page = rank_page(&cdb, &query, &RankingWeights::default(), &options);
+        assert_eq!(page.total, 2);
+        assert_eq!(page.results[0].filename, "report_final.pdf");
+        assert_eq!(page.results[0].duplicates, vec!["/test/report_final (1).pdf"]);
+        assert!(page.results[1].duplicates.is_empty());
+
+        // "report_final (1).pdf" sorts first by name, but has the lower score
+        let options = SearchOptions { collapse_duplicates: true, sort: SortOrder::Name, ..Default::default() };
+        let page = rank_page(&cdb, &query, &RankingWeights::default(), &options);
+        assert_eq!(page.results[1].filename, "report_final.pdf");
+    }
+
+    #[test]
+    fn pages_count_all_matches() {
+        let cdb = CrawlDatabase::init(":memory:");
+        for i in 0..25 {
+            insert_document(&cdb, &format!("invoice_{}.txt", i), &[("invoice", 1.0 - i as f32 / 100.0)]);
+        }
+        let query = Query::parse("invoice");
+
+        let first = rank_page(&cdb, &query, &RankingWeights::default(), &SearchOptions::default());
+        assert_eq!((first.results.len(), first.total), (RESULT_LIMIT, 25));
+
+        let options = SearchOptions { limit: Some(10), offset: 20, ..Default::default() };
+        let last = rank_page(&cdb, &query, &RankingWeights::default(), &options);
+        assert_eq!((last.results.len(), last.total), (5, 25));
+        assert_eq!(last.results[0].filename, "invoice_20.txt");
+    }
+
+    #[test]
+    fn saved_searches_show_only_files_indexed_since_their_last_run() {
+        let cdb = CrawlDatabase::init(":memory:");
+        cdb.save_search("invoices", "invoice");
+        insert_document(&cdb, "before.txt", &[("invoice", 1.0)]);
+        insert_document(&cdb, "after.txt", &[("invoice", 1.0)]);
+        cdb.connection().execute("UPDATE search_index SET indexed_at=100 WHERE filename='before.txt'", ()).unwrap();
+        cdb.connection().execute("UPDATE search_index SET indexed_at=300 WHERE filename='after.txt'", ()).unwrap();
+        cdb.mark_search_run("invoices", 200);
+
+        let saved = cdb.saved_search("invoices").unwrap();
+        assert_eq!((saved.query.as_str(), saved.last_run), ("invoice", Some(200)));
+        let options = SearchOptions { indexed_since: saved.last_run, ..Default::default() };
+        let results = rank(&cdb, &Query::parse(&saved.query), &RankingWeights::default(), &options);
+        assert_eq!(results.into_iter().map(|r| r.filename).collect::<Vec<_>>(), vec!["after.txt"]);
+
+        // Saving again starts over
+        cdb.save_search("invoices", "invoice OR receipt");
+        assert_eq!(cdb.saved_search("invoices").unwrap().last_run, None);
+    }
+
+    #[test]
+    fn like_wildcards_in_filters_are_literal() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "report_2024.txt", &[("budget", 1.0)]);
+        insert_document(&cdb, "reportX2024.txt", &[("budget", 1.0)]);
+        insert_document(&cdb, "discount 50%.txt", &[("budget", 1.0)]);
+        let filenames = |query: &str| -> Vec<String> {
+            let results = rank(&cdb, &Query::parse(query), &RankingWeights::default(), &SearchOptions::default());
+            results.into_iter().map(|r| r.filename).collect()
+        };
+
+        assert_eq!(filenames("budget path:report_2024"), vec!["report_2024.txt"]);
+        assert_eq!(filenames("budget name:50%"), vec!["discount 50%.txt"]);
+        assert_eq!(filenames("budget name:%"), vec!["discount 50%.txt"]);
+    }
+
+    #[test]
+    fn cjk_queries_match_unsegmented_filenames() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "机器学习.pdf", &[("notes", 1.0)]);
+
+        let results = rank(&cdb, &Query::parse("机器学习"), &RankingWeights::default(), &SearchOptions::default());
+        assert_eq!(results.len(), 1);
+        assert!(results[0].explanation.components.iter().any(|c| matches!(c.kind, ComponentKind::Filename { .. })));
+    }
+
+    #[test]
+    fn excluded_documents_do_not_scale_keyword_scores() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "shown.txt", &[("invoice", 0.5)]);
+        insert_document(&cdb, "draft.txt", &[("invoice", 1.0), ("invoice draft", 1.0)]);
+        let path = std::env::temp_dir().join("local_super_search_blend_vectors.txt");
+        std::fs::write(&path, "1 2\ninvoice 1 0\n").unwrap();
+        let model = EmbeddingModel::load(&path, None).unwrap();
+
+        let options = SearchOptions { semantic: Some(SemanticSearch { model: &model, weight: 0.4 }), ..Default::default() };
+        let results = rank(&cdb, &Query::parse("invoice -draft"), &RankingWeights::default(), &options);
+        assert_eq!(results.len(), 1);
+        assert!((results[0].match_score - 0.6).abs() < 1e-5, "{}", results[0].match_score);
+    }
+
+    #[test]
+    fn parse_query_syntax() {
+        let query = Query::parse(r#"budget OR forecast "annual report" -draft type:pdf modified:>2024-01-01 -path:archive/"#);
+
+        assert_eq!(
+            query.groups,
+            vec![
+                vec![Term::Word("budget".into()), Term::Word("forecast".into())],
+                vec![Term::Phrase("annual report".into())],
+            ]
+        );
+        assert_eq!(query.excluded, vec![Term::Word("draft".into())]);
+        assert_eq!(
+            query.filters,
+            vec![
+                Filter { kind: FilterKind::Type(FileType::PDF), negated: false },
+                Filter { kind: FilterKind::Modified(Comparison::After, 1704067200000), negated: false },
+                Filter { kind: FilterKind::Path("archive/".into()), negated: true },
+            ]
+        );
+    }
+
+    #[test]
+    fn terms_are_anded_and_excludes_applied() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "a.txt", &[("budget", 1.0), ("marketing", 1.0)]);
+        insert_document(&cdb, "b.txt", &[("budget", 1.0), ("draft", 1.0)]);
+        insert_document(&cdb, "c.md", &[("marketing", 1.0)]);
+
+        let names = |q: &str| -> Vec<String> {
+            let mut names: Vec<String> = rank(&cdb, &Query::parse(q), &RankingWeights::default(), &SearchOptions::default())
+                .into_iter()
+                .map(|r| r.filename)
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names("budget marketing"), vec!["a.txt"]);
+        assert_eq!(names("budget -draft"), vec!["a.txt"]);
+        assert_eq!(names("budget OR marketing ext:md"), vec!["c.md"]);
+    }
+
+    #[test]
+    fn misspelled_terms_are_expanded() {
+        let mut cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "cluster.md", &[("kubernetes cluster", 1.0)]);
+        insert_document(&cdb, "other.md", &[("holiday", 1.0)]);
+        cdb.rebuild_vocabulary();
+
+        let mut query = Query::parse("kubernets");
+        let corrections = query.correct_typos(cdb.connection());
+        assert_eq!(corrections.len(), 1);
+        assert_eq!(corrections[0].replacements, vec!["kubernetes"]);
+
+        let results = rank(&cdb, &query, &RankingWeights::default(), &SearchOptions::default());
+        assert_eq!(results[0].filename, "cluster.md");
+    }
+
+    #[test]
+    fn numbers_in_the_vocabulary_correct_typos() {
+        let mut cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "taxes.md", &[("20245 tax return", 1.0)]);
+        cdb.rebuild_vocabulary();
+
+        let mut query = Query::parse("20246");
+        let corrections = query.correct_typos(cdb.connection());
+        assert_eq!(corrections[0].replacements, vec!["20245"]);
+    }
+
+    #[test]
+    fn other_word_forms_match_through_stems() {
+        let cdb = CrawlDatabase::init(":memory:");
+        let conn = cdb.connection();
+        conn.execute(
+            "INSERT INTO search_index (filename, filetype, filepath, last_modified_timestamp, language) VALUES ('bills.txt', 12, '/test/bills.txt', 0, 'English')",
+            (),
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO keywords (si_id, word, score, normalized) VALUES (?1, 'invoice', 1.0, 'invoic')",
+            (conn.last_insert_rowid(),),
+        )
+        .unwrap();
+
+        let results = rank(&cdb, &Query::parse("invoices"), &RankingWeights::default(), &SearchOptions::default());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].filename, "bills.txt");
+    }
+
+    #[test]
+    fn accents_in_filenames_are_folded() {
+        let cdb = CrawlDatabase::init(":memory:");
+        cdb.connection()
+            .execute(
+                "INSERT INTO search_index (filename, filetype, filepath, last_modified_timestamp, filename_normalized) VALUES ('Café_Menü.txt', 12, '/test/Café_Menü.txt', 0, 'cafe_menu.txt')",
+                (),
+            )
+            .unwrap();
+
+        for query in ["cafe", "CAFÉ", "menu"] {
+            let results = rank(&cdb, &Query::parse(query), &RankingWeights::default(), &SearchOptions::default());
+            assert_eq!(results.len(), 1, "{}", query);
+        }
+    }
+
+    #[test]
+    fn synonyms_and_acronyms_expand_with_lower_weight() {
+        let cdb = CrawlDatabase::init(":memory:");
+        insert_document(&cdb, "cluster.txt", &[("kubernetes", 1.0)]);
+        insert_document(&cdb, "notes.txt", &[("k8s", 1.0)]);
+        insert_document(&cdb, "review.txt", &[("pull request", 1.0)]);
+        cdb.connection()
+            .execute("INSERT INTO acronyms (si_id, acronym, expansion) VALUES (3, 'PR', 'pull request')", ())
+            .unwrap();
+
+        let synonyms = Synonyms::parse("k8s, kubernetes");
+        let mut query = Query::parse("k8s");
+        query.expand_synonyms(cdb.connection(), Some(&synonyms), 0.5);
+        let names: Vec<String> = rank(&cdb, &query, &RankingWeights::default(), &SearchOptions::default())
+            .into_iter()
+            .map(|r| r.filename)
+            .collect();
+        assert_eq!(names, vec!["notes.txt", "cluster.txt"]);
+
+        let mut query = Query::parse("pr");
+        query.expand_synonyms(cdb.connection(), None, 0.5);
+        assert_eq!(query.groups[0], vec![Term::Word(String::from("pr")), Term::Phrase(String::from("pull request"))]);
+    }
 }
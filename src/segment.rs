@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use jieba_rs::Jieba;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    // Loading the dictionary takes a moment, so it is only done once and only when needed
+    static ref JIEBA: Jieba = Jieba::new();
+}
+
+// Korean attaches particles to words, stripping them gives the same word for "회의는" and "회의를"
+const KOREAN_PARTICLES: &[&str] = &[
+    "에서는", "으로는", "에게서", "까지는", "부터는", "입니다", "에서", "으로", "에게", "한테",
+    "께서", "까지", "부터", "처럼", "보다", "이다", "은", "는", "이", "가", "을", "를", "의", "에",
+    "로", "와", "과", "도", "만",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Script {
+    Han,
+    Hiragana,
+    Katakana,
+    Hangul,
+    Other,
+}
+
+fn script(c: char) -> Script {
+    match c as u32 {
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x20000..=0x2FA1F => Script::Han,
+        0x3040..=0x309F => Script::Hiragana,
+        // The prolonged sound mark ー belongs to katakana words
+        0x30A0..=0x30FF | 0x31F0..=0x31FF | 0xFF66..=0xFF9F => Script::Katakana,
+        0xAC00..=0xD7AF | 0x1100..=0x11FF | 0x3130..=0x318F => Script::Hangul,
+        _ => Script::Other,
+    }
+}
+
+pub fn contains_cjk(text: &str) -> bool {
+    text.chars().any(|c| script(c) != Script::Other)
+}
+
+fn strip_korean_particle(word: &str) -> &str {
+    for particle in KOREAN_PARTICLES {
+        if let Some(stem) = word.strip_suffix(particle) {
+            // Keep short words intact, their last syllable is more likely part of the word
+            if stem.chars().count() >= 2 {
+                return stem;
+            }
+        }
+    }
+    word
+}
+
+/// Splits a run of a single CJK script into words
+fn segment_run(run: &str, run_script: Script, words: &mut Vec<String>) {
+    match run_script {
+        Script::Han => {
+            for token in JIEBA.cut(run, true) {
+                words.push(token.word.to_string());
+            }
+        }
+        Script::Hangul => words.push(strip_korean_particle(run).to_string()),
+        // Katakana runs are mostly loan words, hiragana runs particles and inflections
+        Script::Hiragana | Script::Katakana => words.push(run.to_string()),
+        Script::Other => unreachable!(),
+    }
+}
+
+/// CJK text has no spaces between words, but TextRank and our queries split on whitespace.
+/// This inserts spaces between the words of CJK runs, using the jieba dictionary for Han
+/// characters and script changes for Japanese kana. Everything else is left untouched, apart
+/// from CJK punctuation which is turned into its ASCII counterpart.
+pub fn segment(text: &str) -> String {
+    let mut output = String::with_capacity(text.len() + text.len() / 2);
+    let mut words: Vec<String> = vec![];
+    let mut run = String::new();
+    let mut run_script = Script::Other;
+
+    let mut flush = |run: &mut String, run_script: Script, output: &mut String| {
+        if run.is_empty() {
+            return;
+        }
+        words.clear();
+        segment_run(run, run_script, &mut words);
+        if !output.is_empty() && !output.ends_with(char::is_whitespace) {
+            output.push(' ');
+        }
+        output.push_str(&words.join(" "));
+        output.push(' ');
+        run.clear();
+    };
+
+    for c in text.chars() {
+        let c_script = script(c);
+        if c_script != run_script || c_script == Script::Other {
+            flush(&mut run, run_script, &mut output);
+        }
+        run_script = c_script;
+
+        match c_script {
+            Script::Other => match c {
+                '。' | '．' => output.push_str(". "),
+                '、' | '，' => output.push_str(", "),
+                '！' => output.push_str("! "),
+                '？' => output.push_str("? "),
+                '：' => output.push_str(": "),
+                '；' => output.push_str("; "),
+                '「' | '」' | '『' | '』' | '（' | '）' | '【' | '】' => output.push(' '),
+                c => output.push(c),
+            },
+            _ => run.push(c),
+        }
+    }
+    flush(&mut run, run_script, &mut output);
+
+    output
+}
+
+const ALIAS_PREFIX: &str = "cjkalias";
+
+/// Segmented CJK text with every word replaced by an ASCII placeholder. Tokenizers following
+/// unicode word bounds split Han text into single characters regardless of spaces, the
+/// placeholders survive them and can be turned back into words afterwards.
+pub struct WordAliases {
+    text: String,
+    words: Vec<String>,
+}
+
+impl WordAliases {
+    /// Stop words are kept as they are, so they still break phrases apart
+    pub fn of_cjk_words(segmented: &str, stop_words: &[String]) -> Self {
+        let mut lookup: HashMap<&str, usize> = HashMap::new();
+        let mut words: Vec<String> = vec![];
+
+        let text = segmented
+            .split(' ')
+            .map(|word| {
+                if !contains_cjk(word) || stop_words.iter().any(|s| s == word) {
+                    return word.to_string();
+                }
+                let index = *lookup.entry(word).or_insert_with(|| {
+                    words.push(word.to_string());
+                    words.len() - 1
+                });
+                format!("{}{}", ALIAS_PREFIX, index)
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        Self { text, words }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn restore(&self, phrase: &str) -> String {
+        phrase
+            .split_whitespace()
+            .map(|token| {
+                token
+                    .strip_prefix(ALIAS_PREFIX)
+                    .and_then(|i| i.parse::<usize>().ok())
+                    .and_then(|i| self.words.get(i))
+                    .map_or(token, |w| w.as_str())
+            })
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+}
+
+/// Removes the spaces `segment` put between CJK words, for matching text that was never
+/// segmented like filenames and titles
+pub fn unsegment(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut output = String::with_capacity(text.len());
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_whitespace() {
+            let before = output.chars().last().is_some_and(|b| script(b) != Script::Other);
+            let after = chars[i..].iter().find(|a| !a.is_whitespace()).is_some_and(|a| script(*a) != Script::Other);
+            if before && after {
+                continue;
+            }
+        }
+        output.push(*c);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chinese_is_split_into_words() {
+        let segmented = segment("我们在研究机器学习");
+        assert!(segmented.contains("机器"), "{}", segmented);
+        assert!(segmented.split_whitespace().count() > 2);
+    }
+
+    #[test]
+    fn japanese_splits_on_script_changes() {
+        let segmented = segment("東京でコンピューターを買いました。");
+        let words: Vec<&str> = segmented.split_whitespace().collect();
+        assert!(words.contains(&"コンピューター"), "{:?}", words);
+        assert!(words.contains(&"東京"), "{:?}", words);
+    }
+
+    #[test]
+    fn unsegment_joins_cjk_words_only() {
+        assert_eq!(unsegment(segment("机器学习").trim()), "机器学习");
+        assert_eq!(unsegment("机器 学习 report 2024"), "机器学习 report 2024");
+    }
+
+    #[test]
+    fn aliases_round_trip() {
+        let aliases = WordAliases::of_cjk_words("机器 学习 的 方法", &[String::from("的")]);
+        assert_eq!(aliases.text(), "cjkalias0 cjkalias1 的 cjkalias2");
+        assert_eq!(aliases.restore("cjkalias0 cjkalias1"), "机器 学习");
+    }
+
+    #[test]
+    fn latin_text_is_untouched() {
+        assert_eq!(segment("plain text, nothing else"), "plain text, nothing else");
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::fuzzy;
+
+/// Static word embeddings in the word2vec/GloVe text format, one `word v1 v2 … vn` line per
+/// word with an optional `count dimensions` header. Everything stays local, texts are embedded
+/// as the average of their word vectors.
+pub struct EmbeddingModel {
+    dimensions: usize,
+    vectors: HashMap<String, Vec<f32>>,
+}
+
+impl EmbeddingModel {
+    /// Loads the model, with `only` just the vectors of these words. Searches only need the
+    /// words of the query, which saves reading hundreds of megabytes into memory.
+    pub fn load(path: &Path, only: Option<&HashSet<String>>) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("Cannot open embedding model {:?}: {}", path, e))?;
+
+        let mut dimensions = 0;
+        let mut vectors = HashMap::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("Cannot read embedding model {:?}: {}", path, e))?;
+            let mut parts = line.split(' ');
+            let Some(word) = parts.next() else { continue };
+
+            if only.is_some_and(|words| !words.contains(word)) {
+                continue;
+            }
+            let vector: Vec<f32> = match parts.filter(|p| !p.is_empty()).map(str::parse).collect() {
+                Ok(v) => v,
+                Err(_) => return Err(format!("Malformed line {} in embedding model {:?}", i + 1, path)),
+            };
+            // The word2vec header has a single number after the word count
+            if i == 0 && vector.len() == 1 {
+                continue;
+            }
+            if dimensions == 0 {
+                dimensions = vector.len();
+            }
+            if vector.len() == dimensions {
+                vectors.insert(word.to_lowercase(), vector);
+            }
+        }
+
+        Ok(Self { dimensions, vectors })
+    }
+
+    /// Unit length average of the vectors of all known words, None without any known word
+    pub fn embed(&self, text: &str) -> Option<Vec<f32>> {
+        self.embed_weighted(fuzzy::tokens(text).map(|t| (t, 1.0)))
+    }
+
+    pub fn embed_weighted(&self, words: impl Iterator<Item = (String, f32)>) -> Option<Vec<f32>> {
+        let mut sum = vec![0.0; self.dimensions];
+        let mut found = false;
+        for (word, weight) in words {
+            if let Some(vector) = self.vectors.get(&word) {
+                found = true;
+                for (s, v) in sum.iter_mut().zip(vector) {
+                    *s += v * weight;
+                }
+            }
+        }
+
+        let length = sum.iter().map(|v| v * v).sum::<f32>().sqrt();
+        (found && length > 0.0).then(|| sum.into_iter().map(|v| v / length).collect())
+    }
+}
+
+/// Both vectors are unit length, so this is their cosine similarity
+pub fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similar_texts_are_close() {
+        let path = std::env::temp_dir().join("local_super_search_test_vectors.txt");
+        std::fs::write(
+            &path,
+            "4 3\ninvoice 1 0.1 0\nbill 0.9 0.2 0\npayment 0.8 0.3 0.1\nholiday 0 0.1 1\n",
+        )
+        .unwrap();
+
+        let model = EmbeddingModel::load(&path, None).unwrap();
+        let query = model.embed("Bill").unwrap();
+        let finance = model.embed("invoice payment").unwrap();
+        let holiday = model.embed("holiday").unwrap();
+        assert!(similarity(&query, &finance) > similarity(&query, &holiday));
+        assert_eq!(from_blob(&to_blob(&query)), query);
+        assert!(model.embed("unknown words").is_none());
+
+        let only = HashSet::from([String::from("holiday")]);
+        let partial = EmbeddingModel::load(&path, Some(&only)).unwrap();
+        assert!(partial.embed("invoice").is_none() && partial.embed("holiday").is_some());
+    }
+}
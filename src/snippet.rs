@@ -0,0 +1,318 @@
+use std::ops::Range;
+
+use lingua::Language;
+
+use crate::normalize::{fold, query_forms};
+use crate::segment::contains_cjk;
+
+// Passages end at blank lines or once they get longer than this
+const PASSAGE_MAX_BYTES: usize = 600;
+// Snippets show this many characters around the first match
+const SNIPPET_CHARS: usize = 160;
+const SNIPPET_CONTEXT_CHARS: usize = 40;
+// Stems shorter than this would highlight half the passage
+const MIN_PREFIX_MATCH: usize = 3;
+
+pub const PAGE_BREAK: char = '\x0c';
+
+const HIGHLIGHT_START: &str = "\x1b[1;33m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+/// A paragraph of the extracted text. `start` and `end` are byte offsets into the text,
+/// `line` is the 1-based line the passage starts on. Pages are separated by form feeds, like
+/// in PDF text, other files are a single page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Passage {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub page: usize,
+    pub text: String,
+}
+
+fn floor_boundary(text: &str, mut index: usize) -> usize {
+    index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Splits text into passages at blank lines. Paragraphs longer than `PASSAGE_MAX_BYTES` are cut
+/// at line ends, and single overlong lines at whitespace.
+pub fn split_passages(text: &str) -> Vec<Passage> {
+    let mut ranges: Vec<(usize, usize, usize)> = vec![];
+    let mut current: Option<(usize, usize, usize)> = None;
+    let mut offset = 0;
+
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        let line_number = i + 1;
+        let line_start = offset;
+        offset += line.len();
+
+        if line.trim().is_empty() {
+            ranges.extend(current.take());
+            continue;
+        }
+
+        let content_end = line_start + line.trim_end().len();
+        let mut start = line_start + (line.len() - line.trim_start().len());
+
+        while content_end - start > PASSAGE_MAX_BYTES {
+            ranges.extend(current.take());
+            let limit = floor_boundary(text, start + PASSAGE_MAX_BYTES);
+            let cut = text[start..limit]
+                .rfind(char::is_whitespace)
+                .map(|i| start + i)
+                .filter(|c| *c > start)
+                .unwrap_or(limit);
+            ranges.push((start, cut, line_number));
+            start = content_end - text[cut..content_end].trim_start().len();
+        }
+
+        match &mut current {
+            Some((passage_start, end, _)) if content_end - *passage_start <= PASSAGE_MAX_BYTES => {
+                *end = content_end;
+            }
+            _ => {
+                ranges.extend(current.take());
+                current = Some((start, content_end, line_number));
+            }
+        }
+    }
+    ranges.extend(current);
+
+    let mut page = 1;
+    let mut counted_until = 0;
+    ranges
+        .into_iter()
+        .map(|(start, end, line)| {
+            page += text[counted_until..start].matches(PAGE_BREAK).count();
+            counted_until = start;
+            Passage { start, end, line, page, text: text[start..end].to_string() }
+        })
+        .collect()
+}
+
+/// The part of a passage shown below a result, `highlights` are byte ranges into `text`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub line: usize,
+    pub page: usize,
+    pub text: String,
+    pub highlights: Vec<Range<usize>>,
+}
+
+impl Snippet {
+    /// With `highlight` matches are printed bold, for terminals
+    pub fn render(&self, highlight: bool) -> String {
+        if !highlight {
+            return self.text.clone();
+        }
+        let mut rendered = String::with_capacity(self.text.len() + self.highlights.len() * 12);
+        for (part, matched) in self.segments() {
+            match matched {
+                true => {
+                    rendered.push_str(HIGHLIGHT_START);
+                    rendered.push_str(part);
+                    rendered.push_str(HIGHLIGHT_END);
+                }
+                false => rendered.push_str(part),
+            }
+        }
+        rendered
+    }
+
+    /// The text in consecutive parts, each with whether it is a match
+    pub fn segments(&self) -> Vec<(&str, bool)> {
+        let mut segments = vec![];
+        let mut position = 0;
+        // Highlights are sorted and don't overlap, see `merge_ranges`
+        for range in &self.highlights {
+            if range.start > position {
+                segments.push((&self.text[position..range.start], false));
+            }
+            segments.push((&self.text[range.clone()], true));
+            position = range.end;
+        }
+        if position < self.text.len() {
+            segments.push((&self.text[position..], false));
+        }
+        segments
+    }
+}
+
+/// Words of the text with their byte ranges, split like our tokens on non alphanumeric characters
+fn words(text: &str) -> impl Iterator<Item = (Range<usize>, &str)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(move |w| {
+            let start = w.as_ptr() as usize - text.as_ptr() as usize;
+            (start..start + w.len(), w)
+        })
+}
+
+/// Byte ranges of every query word found in the text, with the index of the word. Words match
+/// when their folded form starts with the folded or stemmed query word, so "invoices" matches
+/// "invoice" and the other way around.
+fn find_matches(text: &str, query_words: &[String], languages: &[Language]) -> Vec<(Range<usize>, usize)> {
+    let mut matches = vec![];
+
+    for (index, query_word) in query_words.iter().enumerate() {
+        if contains_cjk(query_word) {
+            // CJK text is not split into words here, the word is searched as is
+            for (start, found) in text.match_indices(query_word.as_str()) {
+                matches.push((start..start + found.len(), index));
+            }
+            continue;
+        }
+
+        let forms: Vec<String> = query_forms(query_word, languages)
+            .into_iter()
+            .map(|f| fold(&f, true))
+            .collect();
+        for (range, word) in words(text) {
+            let folded = fold(word, true);
+            let matched = forms.iter().any(|form| {
+                folded == *form || (form.chars().count() >= MIN_PREFIX_MATCH && folded.starts_with(form.as_str()))
+            });
+            if matched {
+                matches.push((range, index));
+            }
+        }
+    }
+
+    matches.sort_by_key(|(range, _)| range.start);
+    matches
+}
+
+/// Picks the passage matching the most distinct query words, then the most matches, and cuts
+/// the snippet around its first match
+pub fn best_snippet(passages: &[Passage], terms: &[&str], languages: &[Language]) -> Option<Snippet> {
+    let query_words: Vec<String> = terms
+        .iter()
+        .flat_map(|t| t.split_whitespace())
+        .map(String::from)
+        .collect();
+
+    let mut best: Option<(usize, usize, usize)> = None;
+    let mut best_matches = vec![];
+    for (index, passage) in passages.iter().enumerate() {
+        let matches = find_matches(&passage.text, &query_words, languages);
+        let mut distinct: Vec<usize> = matches.iter().map(|(_, i)| *i).collect();
+        distinct.sort();
+        distinct.dedup();
+
+        let rank = (distinct.len(), matches.len(), index);
+        let better = match best {
+            None => !matches.is_empty(),
+            Some((d, m, _)) => (rank.0, rank.1) > (d, m),
+        };
+        if better {
+            best = Some(rank);
+            best_matches = matches;
+        }
+    }
+
+    let (_, _, index) = best?;
+    let ranges: Vec<Range<usize>> = best_matches.into_iter().map(|(range, _)| range).collect();
+    Some(cut_snippet(&passages[index], ranges[0].start, &ranges))
+}
+
+/// The start of a passage without highlights, for results that share no word with the query
+pub fn leading_snippet(passage: &Passage) -> Snippet {
+    cut_snippet(passage, 0, &[])
+}
+
+/// Sorts the ranges and joins overlapping ones, like the matches of "invoice" and "invoices"
+/// on the same word
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start < last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+fn cut_snippet(passage: &Passage, first: usize, matches: &[Range<usize>]) -> Snippet {
+    let text = &passage.text;
+    // Start a bit before the first match, at a word boundary
+    let mut start = text[..first]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT_CHARS)
+        .map_or(0, |(i, _)| i);
+    if start > 0 {
+        start = text[start..first].find(char::is_whitespace).map_or(start, |i| start + i + 1);
+    }
+    let end = text[start..]
+        .char_indices()
+        .nth(SNIPPET_CHARS)
+        .map_or(text.len(), |(i, _)| start + i);
+
+    let prefix = if start > 0 { "…" } else { "" };
+    let suffix = if end < text.len() { "…" } else { "" };
+    // Line breaks become spaces, which keeps the byte offsets of the highlights
+    let body = text[start..end].replace(['\n', '\r', '\t'], " ");
+
+    let highlights = merge_ranges(
+        matches
+            .iter()
+            .filter(|range| range.start >= start && range.end <= end)
+            .map(|range| range.start - start + prefix.len()..range.end - start + prefix.len())
+            .collect(),
+    );
+
+    Snippet {
+        line: passage.line + text[..first].matches('\n').count(),
+        page: passage.page,
+        text: format!("{}{}{}", prefix, body, suffix),
+        highlights,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passages_keep_offsets_and_lines() {
+        let text = "Title\n\nFirst paragraph\nstill first\n\n\nSecond paragraph\n";
+        let passages = split_passages(text);
+        assert_eq!(passages.len(), 3);
+        assert_eq!(passages[1].text, "First paragraph\nstill first");
+        assert_eq!(passages[1].line, 3);
+        assert_eq!(&text[passages[2].start..passages[2].end], "Second paragraph");
+        assert_eq!(passages[2].line, 7);
+    }
+
+    #[test]
+    fn long_lines_are_cut_at_whitespace() {
+        let text = "word ".repeat(300);
+        let passages = split_passages(&text);
+        assert!(passages.len() > 1);
+        assert!(passages.iter().all(|p| p.text.len() <= PASSAGE_MAX_BYTES && !p.text.starts_with(' ')));
+    }
+
+    #[test]
+    fn snippet_highlights_terms_of_best_passage() {
+        let passages = split_passages("Nothing to see here\n\n\n\x0cThe invoice list\nshows Invoices that are due\n");
+        let snippet = best_snippet(&passages, &["invoice", "due"], &[Language::English]).unwrap();
+        assert_eq!((snippet.line, snippet.page), (4, 2));
+        let highlighted: Vec<&str> = snippet.highlights.iter().map(|r| &snippet.text[r.clone()]).collect();
+        assert_eq!(highlighted, vec!["invoice", "Invoices", "due"]);
+        assert_eq!(snippet.render(false), "The invoice list shows Invoices that are due");
+    }
+
+    #[test]
+    fn overlapping_matches_are_highlighted_once() {
+        let passages = split_passages("All invoices are due\n");
+        let snippet = best_snippet(&passages, &["invoice", "invoices"], &[Language::English]).unwrap();
+        assert_eq!(snippet.highlights, vec![4..12]);
+        assert_eq!(snippet.render(true), format!("All {}invoices{} are due", HIGHLIGHT_START, HIGHLIGHT_END));
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::normalize::fold;
+
+// Longer parentheses are sentences, not acronyms
+const MAX_ACRONYM_LENGTH: usize = 8;
+
+/// User maintained groups of interchangeable terms, read from `synonyms.txt` next to the
+/// config. Every line is one group, separated by commas, `#` starts a comment:
+///
+/// ```text
+/// k8s, kubernetes
+/// pr, pull request, merge request
+/// ```
+#[derive(Debug, Default)]
+pub struct Synonyms {
+    groups: Vec<Vec<String>>,
+}
+
+impl Synonyms {
+    /// A missing file means no synonyms
+    pub fn load(path: &Path) -> Self {
+        match read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let groups = content
+            .lines()
+            .map(|line| line.split('#').next().unwrap())
+            .map(|line| {
+                line.split(',')
+                    .map(|term| term.split_whitespace().collect::<Vec<&str>>().join(" "))
+                    .filter(|term| !term.is_empty())
+                    .collect::<Vec<String>>()
+            })
+            .filter(|group| group.len() >= 2)
+            .collect();
+
+        Self { groups }
+    }
+
+    /// All other terms of the groups containing the term, compared case and accent insensitively
+    pub fn expansions(&self, term: &str) -> Vec<String> {
+        let folded = fold(term, true);
+        let mut expansions: Vec<String> = vec![];
+        for group in &self.groups {
+            if !group.iter().any(|t| fold(t, true) == folded) {
+                continue;
+            }
+            for t in group {
+                if fold(t, true) != folded && !expansions.contains(t) {
+                    expansions.push(t.clone());
+                }
+            }
+        }
+        expansions
+    }
+}
+
+/// Acronyms defined in the text as "Full Name (ACR)", where the words before the parentheses
+/// start with the capitals of the acronym. Returns (acronym, full name) pairs.
+pub fn detect_acronyms(text: &str) -> Vec<(String, String)> {
+    let mut found: Vec<(String, String)> = vec![];
+
+    for (open, _) in text.match_indices('(') {
+        let rest = &text[open + 1..];
+        let Some(close) = rest.find(')') else { continue };
+        let acronym = &rest[..close];
+
+        let capitals: Vec<char> = acronym.chars().filter(|c| c.is_uppercase()).collect();
+        let plausible = acronym.chars().count() <= MAX_ACRONYM_LENGTH
+            && acronym.chars().all(|c| c.is_alphanumeric())
+            && acronym.starts_with(|c: char| c.is_uppercase())
+            && capitals.len() >= 2;
+        if !plausible {
+            continue;
+        }
+
+        let mut words: Vec<&str> = text[..open].split_whitespace().rev().take(capitals.len()).collect();
+        words.reverse();
+        if words.len() < capitals.len() {
+            continue;
+        }
+        let initials_match = words.iter().zip(&capitals).all(|(word, capital)| {
+            word.chars()
+                .find(|c| c.is_alphanumeric())
+                .is_some_and(|first| first.to_uppercase().eq(capital.to_uppercase()))
+        });
+        if !initials_match {
+            continue;
+        }
+
+        let full_name = words
+            .join(" ")
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_string();
+        let pair = (acronym.to_string(), full_name);
+        if !found.contains(&pair) {
+            found.push(pair);
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synonym_groups_expand_both_ways() {
+        let synonyms = Synonyms::parse("# team terms\nk8s, Kubernetes\npr, pull request ,merge request\nlonely\n");
+        assert_eq!(synonyms.expansions("kubernetes"), vec!["k8s"]);
+        assert_eq!(synonyms.expansions("PR"), vec!["pull request", "merge request"]);
+        assert!(synonyms.expansions("lonely").is_empty());
+    }
+
+    #[test]
+    fn acronyms_are_found_in_definitions() {
+        let text = "We store files as Portable Document Format (PDF). Open a pull request (PR) first, \
+            (see below) and the Transmission Control Protocol (TCP).";
+        assert_eq!(
+            detect_acronyms(text),
+            vec![
+                (String::from("PDF"), String::from("Portable Document Format")),
+                (String::from("PR"), String::from("pull request")),
+                (String::from("TCP"), String::from("Transmission Control Protocol")),
+            ]
+        );
+    }
+}
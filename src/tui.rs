@@ -0,0 +1,345 @@
+use std::collections::HashSet;
+use std::env;
+use std::io;
+use std::process::{Command, Stdio};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::crawl::{CrawlDatabase, FileType};
+use crate::open::Openers;
+use crate::search::{format_date, rank, Query, RankingWeights, SearchOptions, SearchResult};
+use crate::snippet::Snippet;
+
+// File types that can be toggled in the filter bar, in this order
+const FILTER_TYPES: [FileType; 11] = [
+    FileType::PDF,
+    FileType::Markdown,
+    FileType::Plain,
+    FileType::Config,
+    FileType::Web,
+    FileType::Python,
+    FileType::JavaScript,
+    FileType::Rust,
+    FileType::CSOURCE,
+    FileType::Excel,
+    FileType::LibreWriter,
+];
+const PREVIEW_KEYWORDS: usize = 10;
+
+/// What the preview pane shows about the selected result besides its snippet
+struct Preview {
+    title: Option<String>,
+    languages: Option<String>,
+    modified: u64,
+    keywords: Vec<String>,
+}
+
+fn load_preview(cdb: &CrawlDatabase, filepath: &str) -> Option<Preview> {
+    let conn = cdb.connection();
+    let (si_id, title, languages, modified): (i64, Option<String>, Option<String>, u64) = conn
+        .query_row(
+            "SELECT id, title, COALESCE(languages, language), last_modified_timestamp FROM search_index WHERE filepath=?1",
+            (filepath,),
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+        )
+        .ok()?;
+
+    let mut stmt = conn
+        .prepare("SELECT word FROM keywords WHERE si_id=?1 ORDER BY score DESC LIMIT ?2")
+        .unwrap();
+    let keywords = stmt
+        .query_map((si_id, PREVIEW_KEYWORDS), |r| r.get(0))
+        .unwrap()
+        .map(|k| k.unwrap())
+        .collect();
+
+    Some(Preview { title, languages, modified, keywords })
+}
+
+struct App<'a> {
+    cdb: &'a CrawlDatabase,
+    weights: &'a RankingWeights,
+    options: SearchOptions<'a>,
+    openers: &'a Openers,
+    input: String,
+    results: Vec<SearchResult>,
+    corrections: Vec<String>,
+    list_state: ListState,
+    preview: Option<Preview>,
+    // Position in FILTER_TYPES while the filter bar has focus
+    filter_cursor: Option<usize>,
+    quit: bool,
+}
+
+impl<'a> App<'a> {
+    /// Runs on every change of the input or filters
+    fn search(&mut self) {
+        let mut query = Query::parse(&self.input);
+        query.expand_synonyms(self.cdb.connection(), self.options.synonyms, self.weights.synonym_weight);
+        self.corrections = query
+            .correct_typos(self.cdb.connection())
+            .into_iter()
+            .map(|c| format!("{} → {}", c.original, c.replacements[0]))
+            .collect();
+
+        self.results = if self.input.trim().is_empty() && self.options.filetypes.is_empty() {
+            vec![]
+        } else {
+            rank(self.cdb, &query, self.weights, &self.options)
+        };
+        self.list_state.select((!self.results.is_empty()).then_some(0));
+        self.update_preview();
+    }
+
+    fn selected(&self) -> Option<&SearchResult> {
+        self.list_state.selected().and_then(|i| self.results.get(i))
+    }
+
+    fn update_preview(&mut self) {
+        self.preview = self.selected().and_then(|sr| load_preview(self.cdb, &sr.filepath));
+    }
+
+    fn move_selection(&mut self, down: bool) {
+        if self.results.is_empty() {
+            return;
+        }
+        let current = self.list_state.selected().unwrap_or(0);
+        let next = if down {
+            (current + 1).min(self.results.len() - 1)
+        } else {
+            current.saturating_sub(1)
+        };
+        self.list_state.select(Some(next));
+        self.update_preview();
+    }
+
+    fn toggle_filter(&mut self, filetype: FileType) {
+        let mut filetypes: HashSet<FileType> = self.options.filetypes.drain(..).collect();
+        if !filetypes.remove(&filetype) {
+            filetypes.insert(filetype);
+        }
+        // Keep the order of the filter bar
+        self.options.filetypes = FILTER_TYPES.into_iter().filter(|t| filetypes.contains(t)).collect();
+        self.search();
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        if let Some(cursor) = self.filter_cursor {
+            match key.code {
+                KeyCode::Left => self.filter_cursor = Some(cursor.saturating_sub(1)),
+                KeyCode::Right => self.filter_cursor = Some((cursor + 1).min(FILTER_TYPES.len() - 1)),
+                KeyCode::Char(' ') => self.toggle_filter(FILTER_TYPES[cursor]),
+                KeyCode::Esc | KeyCode::Enter | KeyCode::Tab => self.filter_cursor = None,
+                KeyCode::Char('c') if ctrl => self.quit = true,
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        match key.code {
+            KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if ctrl => self.quit = true,
+            KeyCode::Tab => self.filter_cursor = Some(0),
+            KeyCode::Up => self.move_selection(false),
+            KeyCode::Down => self.move_selection(true),
+            KeyCode::Enter => {
+                if let Some(sr) = self.selected() {
+                    open_detached(self.openers.command(sr));
+                    self.cdb.record_open(&sr.filepath);
+                }
+            }
+            KeyCode::Char('e') if ctrl => {
+                if let Some(sr) = self.selected() {
+                    let line = sr.snippet.as_ref().map(|s| s.line);
+                    let path = sr.filepath.clone();
+                    self.cdb.record_open(&path);
+                    ratatui::restore();
+                    open_in_editor(&path, line);
+                    *terminal = ratatui::try_init()?;
+                }
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+                self.search();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.input.push(c);
+                self.search();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [input_area, filter_area, main_area, help_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(1),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(main_area);
+
+        let mut input_title = String::from(" Search ");
+        if !self.corrections.is_empty() {
+            input_title = format!(" Search (did you mean {}) ", self.corrections.join(", "));
+        }
+        frame.render_widget(
+            Paragraph::new(format!("{}▏", self.input)).block(Block::default().borders(Borders::ALL).title(input_title)),
+            input_area,
+        );
+
+        let filter_spans: Vec<Span> = FILTER_TYPES
+            .iter()
+            .enumerate()
+            .map(|(i, filetype)| {
+                let mut style = Style::default().fg(Color::DarkGray);
+                if self.options.filetypes.contains(filetype) {
+                    style = Style::default().fg(Color::Green).add_modifier(Modifier::BOLD);
+                }
+                if self.filter_cursor == Some(i) {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                Span::styled(format!(" {} ", filetype.name()), style)
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(Line::from(filter_spans)), filter_area);
+
+        let items: Vec<ListItem> = self.results.iter().map(|sr| ListItem::new(sr.filename.clone())).collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(format!(" {} results ", self.results.len())))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        frame.render_widget(
+            Paragraph::new(self.preview_lines())
+                .wrap(Wrap { trim: false })
+                .block(Block::default().borders(Borders::ALL).title(" Preview ")),
+            preview_area,
+        );
+
+        let help = match self.filter_cursor {
+            Some(_) => "←/→ move  space toggle  esc back",
+            None => "↑/↓ select  enter open  ctrl-e editor  tab filters  esc quit",
+        };
+        frame.render_widget(Paragraph::new(help).style(Style::default().fg(Color::DarkGray)), help_area);
+    }
+
+    fn preview_lines(&self) -> Vec<Line<'static>> {
+        let Some(sr) = self.selected() else { return vec![] };
+        let label = Style::default().fg(Color::Cyan);
+        let mut lines = vec![Line::from(Span::styled(sr.filepath.clone(), Style::default().add_modifier(Modifier::BOLD)))];
+
+        if let Some(snippet) = &sr.snippet {
+            lines.push(Line::default());
+            lines.push(snippet_line(snippet));
+        }
+
+        lines.push(Line::default());
+        lines.push(Line::from(vec![Span::styled("type      ", label), Span::raw(sr.filetype.name())]));
+        lines.push(Line::from(vec![Span::styled("score     ", label), Span::raw(sr.match_score.to_string())]));
+        if let Some(preview) = &self.preview {
+            if let Some(title) = &preview.title {
+                lines.push(Line::from(vec![Span::styled("title     ", label), Span::raw(title.clone())]));
+            }
+            if let Some(languages) = &preview.languages {
+                lines.push(Line::from(vec![Span::styled("language  ", label), Span::raw(languages.clone())]));
+            }
+            lines.push(Line::from(vec![Span::styled("modified  ", label), Span::raw(format_date(preview.modified))]));
+            lines.push(Line::from(vec![Span::styled("keywords  ", label), Span::raw(preview.keywords.join(", "))]));
+        }
+        lines
+    }
+}
+
+/// Snippet with its line number and the matches highlighted
+fn snippet_line(snippet: &Snippet) -> Line<'static> {
+    let mut spans = vec![Span::styled(format!("{}: ", snippet.line), Style::default().fg(Color::DarkGray))];
+    for (part, matched) in snippet.segments() {
+        spans.push(match matched {
+            true => Span::styled(part.to_string(), Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+            false => Span::raw(part.to_string()),
+        });
+    }
+    Line::from(spans)
+}
+
+/// Detached, so the UI keeps running while the file is open
+fn open_detached(mut command: Command) {
+    let _ = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+}
+
+fn open_in_editor(path: &str, line: Option<usize>) {
+    let editor = env::var("EDITOR").unwrap_or_else(|_| String::from("vi"));
+    let mut command = Command::new(editor);
+    if let Some(line) = line {
+        command.arg(format!("+{}", line));
+    }
+    let _ = command.arg(path).status();
+}
+
+/// `is -i`: searches on every keystroke and previews the selected result
+pub fn run(
+    cdb: &CrawlDatabase,
+    initial_query: &str,
+    weights: &RankingWeights,
+    options: SearchOptions,
+    openers: &Openers,
+) -> io::Result<()> {
+    let mut app = App {
+        cdb,
+        weights,
+        options,
+        openers,
+        input: initial_query.to_string(),
+        results: vec![],
+        corrections: vec![],
+        list_state: ListState::default(),
+        preview: None,
+        filter_cursor: None,
+        quit: false,
+    };
+    app.search();
+
+    let mut terminal = ratatui::try_init()?;
+    let result = (|| {
+        while !app.quit {
+            terminal.draw(|frame| app.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    app.handle_key(key, &mut terminal)?;
+                }
+            }
+        }
+        Ok(())
+    })();
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippet_matches_become_highlighted_spans() {
+        let snippet = Snippet { line: 3, page: 1, text: String::from("pay the invoice on time"), highlights: vec![8..15, 16..18] };
+        let line = snippet_line(&snippet);
+        let texts: Vec<&str> = line.spans.iter().map(|s| s.content.as_ref()).collect();
+        assert_eq!(texts, vec!["3: ", "pay the ", "invoice", " ", "on", " time"]);
+        assert!(line.spans[2].style.add_modifier.contains(Modifier::BOLD));
+    }
+}